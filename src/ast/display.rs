@@ -46,6 +46,7 @@ pub fn fmt_list<T: Display>(list: &Vec<T>) -> String {
 
 pub fn get_unparse_mode(args: &crate::Args) -> UnparseMode {
     let crate::Args {
+        command: _,
         input_file: _,
         parse: _,
         unparse,
@@ -53,6 +54,16 @@ pub fn get_unparse_mode(args: &crate::Args) -> UnparseMode {
         check_types: _,
         ac3_IR_generation: _,
         output_assembly: _,
+        optimization: _,
+        emit: _,
+        linker: _,
+        keep_asm: _,
+        pie: _,
+        no_pie: _,
+        runtime: _,
+        runtime_checks: _,
+        backtrace: _,
+        debug_info: _,
     } = args;
 
     match (unparse, named_unparse) {
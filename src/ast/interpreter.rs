@@ -0,0 +1,699 @@
+//! # AST Interpreter
+//! Runs an analyzed program by walking its tree, as the reference for what
+//! programs should do no matter how they get lowered. Locations are resolved
+//! through the symbol table entries name analysis left on them. Integers are
+//! 64 bit, `and`/`or` short circuit, and main's return value is the exit code.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{BufRead, Write},
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+
+use super::{
+    symbol_table::Entry, CallExpression, Class, Declaration, Expression, Function, Location,
+    Primitive, Statement, Type, VariableDeclaration,
+};
+use crate::{
+    err,
+    source_position::{SourcePosition, SourcePositionData},
+    three_ac,
+};
+
+#[derive(Debug, Clone)]
+enum Value {
+    Int(i64),
+    Bool(bool),
+    Str(String),
+    Object(Rc<RefCell<Object>>),
+    Void,
+}
+
+#[derive(Debug)]
+struct Object {
+    class: String,
+    fields: HashMap<String, Value>,
+}
+
+/// What running a statement did to the flow of control
+enum Flow {
+    Next,
+    Return(Value),
+    Exit,
+}
+
+/// A function call in progress
+#[derive(Default)]
+struct Frame {
+    /// Locals and formals by their unique names
+    variables: HashMap<String, Value>,
+    /// The object a method was called on
+    receiver: Option<Rc<RefCell<Object>>>,
+    /// Name of the function, which the globals' frame doesn't have
+    function: Option<String>,
+    /// Where the function's latest call is made from, for backtraces
+    site: Option<SourcePositionData>,
+}
+
+/// Somewhere a value can be stored
+enum Place {
+    Local(String),
+    Global(String),
+    Field(Rc<RefCell<Object>>, String),
+}
+
+struct Interpreter<'a, R: BufRead, W: Write> {
+    functions: HashMap<&'a String, &'a Function>,
+    classes: HashMap<&'a String, &'a Class>,
+    globals: HashMap<String, Value>,
+    frames: Vec<Frame>,
+    /// State of the generator behind 24Kmagic
+    magic: u64,
+    /// Exit code if the program exits before main returns
+    exit_code: i32,
+    /// Whether division by zero and overflow are runtime errors
+    runtime_checks: bool,
+    /// Whether runtime errors print the calls that led to them
+    backtraces: bool,
+    input: R,
+    output: W,
+}
+
+/// Initializes the globals and runs main, returning the exit code. With runtime
+/// checks, division by zero and overflow fail like they do in checked x64, and
+/// with backtraces runtime errors print the calls that led to them.
+pub fn interpret(
+    ast: &[Declaration],
+    runtime_checks: bool,
+    backtraces: bool,
+    input: impl BufRead,
+    output: impl Write,
+) -> Result<i32> {
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+    let mut interpreter = Interpreter {
+        functions: HashMap::new(),
+        classes: HashMap::new(),
+        globals: HashMap::new(),
+        frames: vec![Frame::default()],
+        magic: seed | 1,
+        exit_code: 0,
+        runtime_checks,
+        backtraces,
+        input,
+        output,
+    };
+
+    for declaration in ast {
+        match declaration {
+            Declaration::Class(class) => {
+                interpreter.classes.insert(&class.id.name, class);
+            }
+            Declaration::Function(function) => {
+                interpreter.functions.insert(&function.id.name, function);
+            }
+            Declaration::Variable(_) => (),
+        }
+    }
+
+    let mut code = None;
+    if interpreter.initialize_globals(ast)? {
+        let Some(main) = interpreter.functions.get(&"main".to_string()).copied() else {
+            return err!("FATAL: No main function");
+        };
+
+        code = match interpreter.call(main, Vec::new(), None)? {
+            Some(Value::Int(value)) => Some(value as u8 as i32),
+            Some(_) => Some(0),
+            None => None,
+        };
+    }
+
+    interpreter.output.flush()?;
+    Ok(code.unwrap_or(interpreter.exit_code))
+}
+
+impl<'a, R: BufRead, W: Write> Interpreter<'a, R, W> {
+    /// Reports an error the way the runtime does. The caller then exits.
+    fn runtime_error(&mut self, pos: SourcePositionData, problem: &str) -> Result<()> {
+        self.output.flush()?;
+        eprintln!("runtime error at {pos}: {problem}");
+        if self.backtraces {
+            self.backtrace();
+        }
+        self.exit_code = 1;
+        Ok(())
+    }
+
+    /// Writes the functions running from the innermost out, each with where
+    /// its caller called it from
+    fn backtrace(&self) {
+        for (i, frame) in self.frames.iter().enumerate().rev() {
+            let Some(function) = &frame.function else {
+                continue;
+            };
+
+            // Calls from the globals' initializers are left out, like in the runtime
+            let caller = &self.frames[i - 1];
+            match caller.site.filter(|_| caller.function.is_some()) {
+                Some(site) => eprintln!("  in {function} called at {site}"),
+                None => eprintln!("  in {function}"),
+            }
+        }
+    }
+
+    /// Runs the global declarations in order, returning false if one exits
+    fn initialize_globals(&mut self, ast: &'a [Declaration]) -> Result<bool> {
+        for declaration in ast {
+            let Declaration::Variable(variable) = declaration else {
+                continue;
+            };
+
+            let Some(value) = self.initial_value(variable)? else {
+                return Ok(false);
+            };
+            self.globals.insert(variable.name.name.clone(), value);
+        }
+
+        Ok(true)
+    }
+
+    /// A declared variable's starting value, or None if computing it exits
+    fn initial_value(&mut self, variable: &'a VariableDeclaration) -> Result<Option<Value>> {
+        match &variable.assignment {
+            Some(expression) => self.evaluate(expression),
+            None => self.default_value(&variable.t).map(Some),
+        }
+    }
+
+    fn default_value(&mut self, t: &Type) -> Result<Value> {
+        let value = match t {
+            Type::Primitive(t, _) | Type::PerfectPrimitive(t, _) => match t {
+                Primitive::Bool => Value::Bool(false),
+                Primitive::Int => Value::Int(0),
+                Primitive::String => Value::Str(String::new()),
+                Primitive::Void => Value::Void,
+            },
+            Type::Class(id, _) | Type::PerfectClass(id, _) => {
+                let Some(class) = self.classes.get(&id.name).copied() else {
+                    let pos = id.source_position;
+                    return err!("FATAL {pos}: Undefined type");
+                };
+
+                let mut fields = HashMap::new();
+                for declaration in &class.body {
+                    if let Declaration::Variable(field) = declaration {
+                        let value = match &field.assignment {
+                            Some(expression) => self.evaluate_value(expression)?,
+                            None => self.default_value(&field.t)?,
+                        };
+                        fields.insert(field.name.name.clone(), value);
+                    }
+                }
+
+                let object = Object {
+                    class: id.name.clone(),
+                    fields,
+                };
+                Value::Object(Rc::new(RefCell::new(object)))
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Runs a function with its arguments, giving back its return value, or
+    /// None if the program exits
+    fn call(
+        &mut self,
+        function: &'a Function,
+        args: Vec<Value>,
+        receiver: Option<Rc<RefCell<Object>>>,
+    ) -> Result<Option<Value>> {
+        let mut frame = Frame {
+            variables: HashMap::new(),
+            receiver,
+            function: Some(function.id.name.clone()),
+            site: None,
+        };
+        for (formal, value) in function.fn_input.iter().zip(args) {
+            frame.variables.insert(formal.id.name.clone(), value);
+        }
+
+        self.frames.push(frame);
+        let flow = self.execute_all(&function.body);
+        self.frames.pop();
+
+        match flow? {
+            Flow::Next => Ok(Some(self.default_value(&function.fn_output)?)),
+            Flow::Return(value) => Ok(Some(value)),
+            Flow::Exit => Ok(None),
+        }
+    }
+
+    fn execute_all(&mut self, statements: &'a [Statement]) -> Result<Flow> {
+        for statement in statements {
+            match self.execute(statement)? {
+                Flow::Next => (),
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn execute(&mut self, statement: &'a Statement) -> Result<Flow> {
+        match statement {
+            Statement::Assignment(location, expression) => {
+                let Some(value) = self.evaluate(expression)? else {
+                    return Ok(Flow::Exit);
+                };
+                let place = self.place(location)?;
+                self.store(place, value);
+            }
+            Statement::CallExpression(call) => {
+                if self.evaluate_call(call)?.is_none() {
+                    return Ok(Flow::Exit);
+                }
+            }
+            Statement::Decrement(location) => {
+                if !self.update(location, i64::overflowing_sub)? {
+                    return Ok(Flow::Exit);
+                }
+            }
+            Statement::Exit => return Ok(Flow::Exit),
+            Statement::Give(expression) => {
+                let Some(value) = self.evaluate(expression)? else {
+                    return Ok(Flow::Exit);
+                };
+
+                match value {
+                    Value::Int(value) => write!(self.output, "{value}")?,
+                    Value::Bool(value) => write!(self.output, "{value}")?,
+                    Value::Str(value) => write!(self.output, "{value}")?,
+                    _ => {
+                        let pos = expression.source_position();
+                        return err!("FATAL {pos}: Attempt to output a non-primitive value");
+                    }
+                }
+            }
+            Statement::If(condition, if_, else_) => {
+                let Some(condition) = self.evaluate_bool(condition)? else {
+                    return Ok(Flow::Exit);
+                };
+
+                let body = if condition { if_ } else { else_ };
+                return self.execute_all(&body.statements);
+            }
+            Statement::Increment(location) => {
+                if !self.update(location, i64::overflowing_add)? {
+                    return Ok(Flow::Exit);
+                }
+            }
+            Statement::Return(expression, _) => {
+                let value = match expression {
+                    Some(expression) => match self.evaluate(expression)? {
+                        Some(value) => value,
+                        None => return Ok(Flow::Exit),
+                    },
+                    None => Value::Void,
+                };
+
+                return Ok(Flow::Return(value));
+            }
+            Statement::Take(location) => {
+                let mut line = String::new();
+                self.output.flush()?;
+                self.input.read_line(&mut line)?;
+
+                let value = match self.get_type(location)?.unwrap_primitive() {
+                    Some((Primitive::Bool, _)) => three_ac::parse_bool(&line).map(Value::Bool),
+                    _ => three_ac::parse_int(&line).map(Value::Int),
+                };
+
+                let value = match value {
+                    Ok(value) => value,
+                    Err(problem) => {
+                        self.runtime_error(location.source_position(), problem)?;
+                        return Ok(Flow::Exit);
+                    }
+                };
+
+                let place = self.place(location)?;
+                self.store(place, value);
+            }
+            Statement::VariableDeclaration(Declaration::Variable(variable)) => {
+                let Some(value) = self.initial_value(variable)? else {
+                    return Ok(Flow::Exit);
+                };
+
+                let frame = self.frames.last_mut().unwrap();
+                frame.variables.insert(variable.unique_name(), value);
+            }
+            Statement::VariableDeclaration(_) => (),
+            Statement::While(condition, body) => loop {
+                let Some(condition) = self.evaluate_bool(condition)? else {
+                    return Ok(Flow::Exit);
+                };
+                if !condition {
+                    break;
+                }
+
+                match self.execute_all(&body.statements)? {
+                    Flow::Next => (),
+                    flow => return Ok(flow),
+                }
+            },
+        }
+
+        Ok(Flow::Next)
+    }
+
+    /// Adds or subtracts one
+    fn update(&mut self, location: &'a Location, f: fn(i64, i64) -> (i64, bool)) -> Result<bool> {
+        let place = self.place(location)?;
+        match self.load(&place) {
+            Value::Int(value) => {
+                let pos = location.source_position();
+                let Some(value) = self.checked(f(value, 1), pos)? else {
+                    return Ok(false);
+                };
+
+                self.store(place, Value::Int(value));
+                Ok(true)
+            }
+            _ => {
+                let pos = location.source_position();
+                err!("FATAL {pos}: Arithmetic operator applied to invalid operand")
+            }
+        }
+    }
+
+    /// Evaluates an expression, or gives None if the program exits while doing so
+    fn evaluate(&mut self, expression: &'a Expression) -> Result<Option<Value>> {
+        use Expression::*;
+
+        let value = match expression {
+            Add(a, b) => self.arithmetic(expression, a, b, i64::overflowing_add)?,
+            And(a, b) => match self.evaluate_bool(a)? {
+                Some(true) => self.evaluate_bool(b)?.map(Value::Bool),
+                Some(false) => Some(Value::Bool(false)),
+                None => None,
+            },
+            CallExpression(call) => self.evaluate_call(call)?,
+            Divide(a, b) => {
+                let Some(x) = self.evaluate_int(a)? else {
+                    return Ok(None);
+                };
+                let Some(y) = self.evaluate_int(b)? else {
+                    return Ok(None);
+                };
+
+                let pos = expression.source_position();
+                if y == 0 && self.runtime_checks {
+                    self.runtime_error(pos, "division by zero")?;
+                    return Ok(None);
+                } else if y == 0 {
+                    return err!("FATAL {pos}: Division by zero");
+                }
+                self.checked(x.overflowing_div(y), pos)?.map(Value::Int)
+            }
+            Equals(a, b) => self.equality(a, b)?.map(Value::Bool),
+            False(_) => Some(Value::Bool(false)),
+            Greater(a, b) => self.comparison(a, b, |a, b| a > b)?,
+            GreaterEq(a, b) => self.comparison(a, b, |a, b| a >= b)?,
+            IntegerLiteral(value, _) => Some(Value::Int(*value as i64)),
+            Less(a, b) => self.comparison(a, b, |a, b| a < b)?,
+            LessEq(a, b) => self.comparison(a, b, |a, b| a <= b)?,
+            Location(location) => {
+                let place = self.place(location)?;
+                Some(self.load(&place))
+            }
+            Magic(_) => Some(Value::Bool(self.next_magic())),
+            Multiply(a, b) => self.arithmetic(expression, a, b, i64::overflowing_mul)?,
+            Negative(a) => match self.evaluate_int(a)? {
+                Some(a) => {
+                    let pos = expression.source_position();
+                    self.checked(a.overflowing_neg(), pos)?.map(Value::Int)
+                }
+                None => None,
+            },
+            Not(a) => self.evaluate_bool(a)?.map(|a| Value::Bool(!a)),
+            NotEquals(a, b) => self.equality(a, b)?.map(|equal| Value::Bool(!equal)),
+            Or(a, b) => match self.evaluate_bool(a)? {
+                Some(false) => self.evaluate_bool(b)?.map(Value::Bool),
+                Some(true) => Some(Value::Bool(true)),
+                None => None,
+            },
+            StringLiteral(value, _) => Some(Value::Str(three_ac::unescape(value))),
+            Subtract(a, b) => self.arithmetic(expression, a, b, i64::overflowing_sub)?,
+            True(_) => Some(Value::Bool(true)),
+        };
+
+        Ok(value)
+    }
+
+    /// Evaluates an expression that can't exit, like a field's initializer
+    fn evaluate_value(&mut self, expression: &'a Expression) -> Result<Value> {
+        match self.evaluate(expression)? {
+            Some(value) => Ok(value),
+            None => {
+                let pos = expression.source_position();
+                err!("FATAL {pos}: Exit while initializing a field")
+            }
+        }
+    }
+
+    fn evaluate_int(&mut self, expression: &'a Expression) -> Result<Option<i64>> {
+        match self.evaluate(expression)? {
+            Some(Value::Int(value)) => Ok(Some(value)),
+            None => Ok(None),
+            Some(_) => {
+                let pos = expression.source_position();
+                err!("FATAL {pos}: Expected an int")
+            }
+        }
+    }
+
+    fn evaluate_bool(&mut self, expression: &'a Expression) -> Result<Option<bool>> {
+        match self.evaluate(expression)? {
+            Some(Value::Bool(value)) => Ok(Some(value)),
+            None => Ok(None),
+            Some(_) => {
+                let pos = expression.source_position();
+                err!("FATAL {pos}: Expected a bool")
+            }
+        }
+    }
+
+    fn arithmetic(
+        &mut self,
+        expression: &'a Expression,
+        a: &'a Expression,
+        b: &'a Expression,
+        f: fn(i64, i64) -> (i64, bool),
+    ) -> Result<Option<Value>> {
+        let Some(a) = self.evaluate_int(a)? else {
+            return Ok(None);
+        };
+        let Some(b) = self.evaluate_int(b)? else {
+            return Ok(None);
+        };
+
+        let pos = expression.source_position();
+        Ok(self.checked(f(a, b), pos)?.map(Value::Int))
+    }
+
+    /// Gives the result of an operation that may have overflowed, which wraps
+    /// unless runtime checks are on. Then it's an error, and None.
+    fn checked(&mut self, result: (i64, bool), pos: SourcePositionData) -> Result<Option<i64>> {
+        match result {
+            (_, true) if self.runtime_checks => {
+                self.runtime_error(pos, "integer overflow")?;
+                Ok(None)
+            }
+            (value, _) => Ok(Some(value)),
+        }
+    }
+
+    fn comparison(
+        &mut self,
+        a: &'a Expression,
+        b: &'a Expression,
+        f: fn(i64, i64) -> bool,
+    ) -> Result<Option<Value>> {
+        let Some(a) = self.evaluate_int(a)? else {
+            return Ok(None);
+        };
+        let Some(b) = self.evaluate_int(b)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(Value::Bool(f(a, b))))
+    }
+
+    fn equality(&mut self, a: &'a Expression, b: &'a Expression) -> Result<Option<bool>> {
+        let Some(x) = self.evaluate(a)? else {
+            return Ok(None);
+        };
+        let Some(y) = self.evaluate(b)? else {
+            return Ok(None);
+        };
+
+        let equal = match (x, y) {
+            (Value::Int(x), Value::Int(y)) => x == y,
+            (Value::Bool(x), Value::Bool(y)) => x == y,
+            (Value::Str(x), Value::Str(y)) => x == y,
+            (Value::Object(x), Value::Object(y)) => Rc::ptr_eq(&x, &y),
+            _ => {
+                let pos = a.source_position();
+                return err!("FATAL {pos}: Invalid equality operand");
+            }
+        };
+
+        Ok(Some(equal))
+    }
+
+    fn evaluate_call(&mut self, call: &'a CallExpression) -> Result<Option<Value>> {
+        let (function, receiver) = self.get_callee(&call.location)?;
+
+        let mut args = Vec::new();
+        for actual in &call.actuals {
+            match self.evaluate(actual)? {
+                Some(value) => args.push(value),
+                None => return Ok(None),
+            }
+        }
+
+        self.frames.last_mut().unwrap().site = Some(call.source_position);
+        self.call(function, args, receiver)
+    }
+
+    /// The function a call refers to, along with the object it's called on
+    fn get_callee(
+        &mut self,
+        location: &'a Location,
+    ) -> Result<(&'a Function, Option<Rc<RefCell<Object>>>)> {
+        let pos = location.source_position();
+        let name = &location.get_last_link().current_link;
+
+        let receiver = match location.next_link {
+            Some(_) => {
+                let place = self.place_of_links(location, false)?;
+                match self.load(&place) {
+                    Value::Object(object) => Some(object),
+                    _ => return err!("FATAL {pos}: Attempt to call a method of a non-class"),
+                }
+            }
+            // Methods can call their siblings without naming the object
+            None => self.frames.last().unwrap().receiver.clone(),
+        };
+
+        if let Some(receiver) = &receiver {
+            let class = receiver.borrow().class.clone();
+            if let Some(method) = self.get_method(&class, name) {
+                return Ok((method, Some(receiver.clone())));
+            }
+        }
+
+        match self.functions.get(name) {
+            Some(function) => Ok((function, None)),
+            None => err!("FATAL {pos}: Attempt to call a non-function"),
+        }
+    }
+
+    fn get_method(&self, class: &String, name: &String) -> Option<&'a Function> {
+        let class = self.classes.get(class)?;
+
+        class.body.iter().find_map(|declaration| match declaration {
+            Declaration::Function(function) if function.id.name == *name => Some(function),
+            _ => None,
+        })
+    }
+
+    /// The type of the variable a location ends at
+    fn get_type(&self, location: &Location) -> Result<Type> {
+        let link = location.get_last_link();
+        match link.get_entry()?.as_ref() {
+            Entry::Variable(t) => Ok(t.clone()),
+            _ => {
+                let pos = location.source_position();
+                err!("FATAL {pos}: Expected a variable")
+            }
+        }
+    }
+
+    fn place(&mut self, location: &Location) -> Result<Place> {
+        self.place_of_links(location, true)
+    }
+
+    /// Where a location's value is kept, following links through objects. The
+    /// last link can be left off to get the object a method belongs to.
+    fn place_of_links(&mut self, location: &Location, last: bool) -> Result<Place> {
+        let frame = self.frames.last().unwrap();
+        let name = &location.current_link;
+
+        let mut place = if !location.is_local() {
+            Place::Global(name.clone())
+        } else if frame.variables.contains_key(&location.unique_name()) {
+            Place::Local(location.unique_name())
+        } else {
+            match &frame.receiver {
+                Some(receiver) => Place::Field(receiver.clone(), name.clone()),
+                None => Place::Local(location.unique_name()),
+            }
+        };
+
+        let mut link = location;
+        while let Some(next) = &link.next_link {
+            if next.next_link.is_none() && !last {
+                break;
+            }
+
+            let Value::Object(object) = self.load(&place) else {
+                let pos = link.source_position();
+                return err!("FATAL {pos}: Attempt to access a field of a non-class");
+            };
+            place = Place::Field(object, next.current_link.clone());
+            link = next;
+        }
+
+        Ok(place)
+    }
+
+    fn load(&self, place: &Place) -> Value {
+        let value = match place {
+            Place::Local(name) => self.frames.last().unwrap().variables.get(name).cloned(),
+            Place::Global(name) => self.globals.get(name).cloned(),
+            Place::Field(object, name) => object.borrow().fields.get(name).cloned(),
+        };
+
+        value.unwrap_or(Value::Void)
+    }
+
+    fn store(&mut self, place: Place, value: Value) {
+        match place {
+            Place::Local(name) => {
+                let frame = self.frames.last_mut().unwrap();
+                frame.variables.insert(name, value);
+            }
+            Place::Global(name) => {
+                self.globals.insert(name, value);
+            }
+            Place::Field(object, name) => {
+                object.borrow_mut().fields.insert(name, value);
+            }
+        }
+    }
+
+    /// A coin flip from an xorshift generator
+    fn next_magic(&mut self) -> bool {
+        self.magic ^= self.magic << 13;
+        self.magic ^= self.magic >> 7;
+        self.magic ^= self.magic << 17;
+        self.magic & 1 == 1
+    }
+}
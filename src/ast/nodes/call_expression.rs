@@ -20,11 +20,22 @@ impl std::fmt::Display for CallExpression {
 impl IRCode for CallExpression {
     fn get_ir_code(&self) -> Vec<Quad> {
         let mut quads = Vec::new();
+        let mut args = Vec::new();
 
-        for i in 0..self.actuals.len() {
-            let actual = &self.actuals[i];
+        // Evaluate every actual before setting any argument, so calls nested
+        // in the actuals can't clobber arguments that were already set
+        for actual in &self.actuals {
             let (mut code, arg) = actual.get_ir_code();
             quads.append(&mut code);
+            args.push(arg);
+        }
+
+        if three_ac::get_backtraces() {
+            let site = three_ac::add_string(&format!(" called at {}", self.source_position));
+            quads.push(Quad::CallSite(site));
+        }
+
+        for (i, arg) in args.into_iter().enumerate() {
             quads.push(Quad::SetArg(i + 1, arg));
         }
 
@@ -1,4 +1,4 @@
-use crate::three_ac::{self, Argument, Quad};
+use crate::three_ac::{self, Argument, Check, Quad};
 
 use super::{
     CallExpression, IRCode, Kind, Kinded, Location, NameAnalysis, Primitive, SourcePosition,
@@ -39,7 +39,14 @@ impl Expression {
     pub fn get_ir_code(&self) -> (Vec<Quad>, Argument) {
         match self {
             Self::Add(a, b) => {
-                let (quads, handles) = get_expression_ir(vec![a, b]);
+                let (mut quads, handles) = get_expression_ir(vec![a, b]);
+                let pos = self.source_position();
+                quads.extend(three_ac::get_check(
+                    Check::Add,
+                    &handles[0],
+                    &handles[1],
+                    pos,
+                ));
                 let operation = Quad::Add(
                     Argument::Local(three_ac::get_tmp()),
                     handles[0].clone(),
@@ -47,26 +54,23 @@ impl Expression {
                 );
                 handle_operation_ir(quads, operation)
             }
-            Self::And(a, b) => {
-                let (quads, handles) = get_expression_ir(vec![a, b]);
-                let operation = Quad::And(
-                    Argument::Local(three_ac::get_tmp()),
-                    handles[0].clone(),
-                    handles[1].clone(),
-                );
-                handle_operation_ir(quads, operation)
-            }
+            Self::And(a, b) => short_circuit_ir(a, b, true),
             Self::CallExpression(call) => (
                 call.get_ir_code(),
                 Argument::Local(three_ac::get_last_tmp()),
             ),
             Self::Divide(a, b) => {
-                let (quads, handles) = get_expression_ir(vec![a, b]);
-                let operation = Quad::Divide(
-                    Argument::Local(three_ac::get_tmp()),
-                    handles[0].clone(),
-                    handles[1].clone(),
-                );
+                let (mut quads, handles) = get_expression_ir(vec![a, b]);
+                let pos = self.source_position();
+                quads.extend(three_ac::get_check(
+                    Check::Divide,
+                    &handles[0],
+                    &handles[1],
+                    pos,
+                ));
+                let location = Argument::Local(three_ac::get_tmp());
+                three_ac::add_division(None, &location, pos);
+                let operation = Quad::Divide(location, handles[0].clone(), handles[1].clone());
                 handle_operation_ir(quads, operation)
             }
             Self::Equals(a, b) => {
@@ -118,14 +122,21 @@ impl Expression {
             }
             Self::Location(loc) => {
                 if loc.is_local() {
-                    (Vec::new(), Argument::Local(format!("{loc}")))
+                    (Vec::new(), Argument::Local(loc.unique_name()))
                 } else {
                     (Vec::new(), Argument::Global(format!("{loc}")))
                 }
             }
             Self::Magic(_) => unimplemented!(),
             Self::Multiply(a, b) => {
-                let (quads, handles) = get_expression_ir(vec![a, b]);
+                let (mut quads, handles) = get_expression_ir(vec![a, b]);
+                let pos = self.source_position();
+                quads.extend(three_ac::get_check(
+                    Check::Multiply,
+                    &handles[0],
+                    &handles[1],
+                    pos,
+                ));
                 let operation = Quad::Multiply(
                     Argument::Local(three_ac::get_tmp()),
                     handles[0].clone(),
@@ -134,7 +145,15 @@ impl Expression {
                 handle_operation_ir(quads, operation)
             }
             Self::Negative(a) => {
-                let (quads, handles) = get_expression_ir(vec![a]);
+                let (mut quads, handles) = get_expression_ir(vec![a]);
+                let zero = Argument::Literal(0);
+                let pos = self.source_position();
+                quads.extend(three_ac::get_check(
+                    Check::Subtract,
+                    &zero,
+                    &handles[0],
+                    pos,
+                ));
                 let operation = Quad::Subtract(
                     Argument::Local(three_ac::get_tmp()),
                     Argument::Literal(0),
@@ -156,22 +175,20 @@ impl Expression {
                 );
                 handle_operation_ir(quads, operation)
             }
-            Self::Or(a, b) => {
-                let (quads, handles) = get_expression_ir(vec![a, b]);
-                let operation = Quad::Or(
-                    Argument::Local(three_ac::get_tmp()),
-                    handles[0].clone(),
-                    handles[1].clone(),
-                );
-                handle_operation_ir(quads, operation)
-            }
+            Self::Or(a, b) => short_circuit_ir(a, b, false),
             Self::StringLiteral(str, _) => {
-                let label = three_ac::get_str();
-                three_ac::add_global(&format!("{label} \"{str}\""));
+                let label = three_ac::add_string(str);
                 (Vec::new(), Argument::Global(label))
             }
             Self::Subtract(a, b) => {
-                let (quads, handles) = get_expression_ir(vec![a, b]);
+                let (mut quads, handles) = get_expression_ir(vec![a, b]);
+                let pos = self.source_position();
+                quads.extend(three_ac::get_check(
+                    Check::Subtract,
+                    &handles[0],
+                    &handles[1],
+                    pos,
+                ));
                 let operation = Quad::Subtract(
                     Argument::Local(three_ac::get_tmp()),
                     handles[0].clone(),
@@ -499,6 +516,31 @@ fn get_expression_ir(expressions: Vec<&Box<Expression>>) -> (Vec<Quad>, Vec<Argu
     (quads, handles)
 }
 
+/// `and`/`or` only evaluate the right operand when the left one doesn't
+/// already decide the result, which is whether it's `and`
+fn short_circuit_ir(a: &Expression, b: &Expression, and: bool) -> (Vec<Quad>, Argument) {
+    let (mut quads, left) = a.get_ir_code();
+    let result = Argument::Local(three_ac::get_tmp());
+    let end_label = three_ac::get_lbl();
+
+    quads.push(Quad::Assignment(result.clone(), left));
+    if and {
+        quads.push(Quad::Ifz(result.clone(), end_label.clone()));
+    } else {
+        let right_label = three_ac::get_lbl();
+        quads.push(Quad::Ifz(result.clone(), right_label.clone()));
+        quads.push(Quad::Goto(end_label.clone()));
+        quads.push(Quad::Label(right_label));
+    }
+
+    let (mut code, right) = b.get_ir_code();
+    quads.append(&mut code);
+    quads.push(Quad::Assignment(result.clone(), right));
+    quads.push(Quad::Label(end_label));
+
+    (quads, result)
+}
+
 fn handle_operation_ir(mut quads: Vec<Quad>, operation: Quad) -> (Vec<Quad>, Argument) {
     quads.push(operation);
     (quads, Argument::Local(three_ac::get_last_tmp()))
@@ -1,7 +1,7 @@
 use super::{symbol_table::Entry::*, *};
 use crate::{
     err,
-    three_ac::{self, Quad},
+    three_ac::{self, IrFunction, Quad},
 };
 
 #[derive(Clone, Debug)]
@@ -34,16 +34,16 @@ impl Function {
             .collect()
     }
 
-    fn get_locals(&self) -> Vec<Id> {
+    fn get_locals(&self) -> Vec<String> {
         Self::get_locals_from_body(&self.body)
     }
 
-    fn get_locals_from_body(body: &Vec<Statement>) -> Vec<Id> {
+    fn get_locals_from_body(body: &Vec<Statement>) -> Vec<String> {
         let mut vec = Vec::new();
 
         for child in body {
             if let Statement::VariableDeclaration(Declaration::Variable(decl)) = child {
-                vec.push(decl.name.clone())
+                vec.push(decl.unique_name())
             }
 
             if let Statement::If(_, b1, b2) = child {
@@ -73,12 +73,10 @@ impl std::fmt::Display for Function {
     }
 }
 
-impl IRCode for Function {
-    fn get_ir_code(&self) -> Vec<Quad> {
+impl Function {
+    pub fn get_ir_function(&self) -> IrFunction {
         let mut body_quads = Vec::new();
         let name = &self.id.name;
-        let fn_name = format!("fn_{name}");
-        three_ac::add_global(&fn_name);
         let exit_label = three_ac::get_new_fn_exit_lbl();
 
         let start_tmps = three_ac::get_tmp_counter();
@@ -87,13 +85,8 @@ impl IRCode for Function {
         }
         let end_tmps = three_ac::get_tmp_counter();
 
-        let mut quads = vec![Quad::Locals(
-            name.clone(),
-            self.fn_input.clone(),
-            self.get_locals(),
-            start_tmps..end_tmps,
-        )];
-        quads.push(Quad::Enter(name.clone()));
+        let mut quads = vec![Quad::Enter(name.clone())];
+        quads.append(&mut three_ac::get_loc(Some(self.id.source_position())));
 
         for i in 0..self.fn_input.len() {
             let name = self.fn_input[i].id.name.clone();
@@ -103,7 +96,13 @@ impl IRCode for Function {
         quads.append(&mut body_quads);
         quads.push(Quad::Leave(exit_label, name.clone()));
 
-        quads
+        IrFunction {
+            name: name.clone(),
+            formals: self.fn_input.iter().map(|f| f.id.name.clone()).collect(),
+            locals: self.get_locals(),
+            temps: start_tmps..end_tmps,
+            body: quads,
+        }
     }
 }
 
@@ -119,7 +118,7 @@ impl NameAnalysis for Function {
         let position = self.id.source_position();
 
         symbol_table.add(&self.id.name, entry, position)?;
-        symbol_table.enter_scope();
+        symbol_table.enter_function();
         Ok(())
     }
 
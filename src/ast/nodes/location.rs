@@ -12,6 +12,7 @@ pub struct Location {
     pub next_link: Option<Box<Location>>,
     pub symbol_table_entry: Option<Rc<symbol_table::Entry>>,
     pub is_local: Option<bool>,
+    pub unique_name: Option<String>,
 }
 
 impl Location {
@@ -23,6 +24,7 @@ impl Location {
             next_link: None,
             symbol_table_entry: None,
             is_local: None,
+            unique_name: None,
         }
     }
 
@@ -57,6 +59,15 @@ impl Location {
 
         local
     }
+
+    /// Name of the declaration this refers to, unique within its function
+    pub fn unique_name(&self) -> String {
+        let Some(name) = self.unique_name.clone() else {
+            unreachable!()
+        };
+
+        name
+    }
 }
 
 impl std::fmt::Display for Location {
@@ -126,6 +137,10 @@ impl NameAnalysis for Location {
         }
 
         self.is_local = Some(symbol_table.is_local(name));
+        self.unique_name = Some(match self.enclosing_class {
+            Some(_) => name.clone(),
+            None => symbol_table.get_unique_name(name).unwrap_or(name.clone()),
+        });
 
         Ok(())
     }
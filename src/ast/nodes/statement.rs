@@ -1,7 +1,7 @@
 use super::{symbol_table::Entry::*, *};
 use crate::{
     err,
-    three_ac::{self, Argument, Quad},
+    three_ac::{self, Argument, Check, Quad},
 };
 
 #[derive(Clone, Debug)]
@@ -46,9 +46,9 @@ impl Statement {
             Self::Return(_, _) => Ok(()), // Return checking is done in function declaration
             Self::Take(x) => check_take(x),
             Self::VariableDeclaration(Declaration::Variable(VariableDeclaration {
-                name: _,
                 t,
                 assignment,
+                ..
             })) => check_var_decl(t, assignment),
             _ => unreachable!(),
         }
@@ -79,11 +79,35 @@ impl std::fmt::Display for Statement {
 
 impl IRCode for Statement {
     fn get_ir_code(&self) -> Vec<Quad> {
+        let mut quads = three_ac::get_loc(self.position());
+        quads.append(&mut self.get_statement_ir());
+        quads
+    }
+}
+
+impl Statement {
+    /// Where the statement starts, as far as the tree knows. Exiting doesn't
+    /// keep a position.
+    fn position(&self) -> Option<SourcePositionData> {
+        match self {
+            Self::Assignment(x, _) | Self::Decrement(x) | Self::Increment(x) | Self::Take(x) => {
+                Some(x.source_position())
+            }
+            Self::CallExpression(x) => Some(x.source_position()),
+            Self::Exit => None,
+            Self::Give(x) | Self::If(x, _, _) | Self::While(x, _) => Some(x.source_position()),
+            Self::Return(_, pos) => Some(*pos),
+            Self::VariableDeclaration(Declaration::Variable(x)) => Some(x.name.source_position()),
+            Self::VariableDeclaration(_) => None,
+        }
+    }
+
+    fn get_statement_ir(&self) -> Vec<Quad> {
         match self {
             Self::Assignment(loc, x) => {
                 let (mut quads, arg) = x.get_ir_code();
                 if loc.is_local() {
-                    quads.push(Quad::Assignment(Argument::Local(format!("{loc}")), arg));
+                    quads.push(Quad::Assignment(Argument::Local(loc.unique_name()), arg));
                 } else {
                     quads.push(Quad::Assignment(Argument::Global(format!("{loc}")), arg));
                 }
@@ -93,11 +117,15 @@ impl IRCode for Statement {
             Self::Decrement(loc) => {
                 let arg;
                 if loc.is_local() {
-                    arg = Argument::Local(format!("{loc}"));
+                    arg = Argument::Local(loc.unique_name());
                 } else {
                     arg = Argument::Global(format!("{loc}"));
                 }
-                vec![Quad::Subtract(arg.clone(), arg, Argument::Literal(1))]
+                let one = Argument::Literal(1);
+                let pos = loc.source_position();
+                let mut quads = three_ac::get_check(Check::Subtract, &arg, &one, pos);
+                quads.push(Quad::Subtract(arg.clone(), arg, one));
+                quads
             }
             Self::Exit => vec![Quad::Exit],
             Self::Give(x) => {
@@ -144,11 +172,15 @@ impl IRCode for Statement {
             Self::Increment(loc) => {
                 let arg;
                 if loc.is_local() {
-                    arg = Argument::Local(format!("{loc}"));
+                    arg = Argument::Local(loc.unique_name());
                 } else {
                     arg = Argument::Global(format!("{loc}"));
                 }
-                vec![Quad::Add(arg.clone(), arg, Argument::Literal(1))]
+                let one = Argument::Literal(1);
+                let pos = loc.source_position();
+                let mut quads = three_ac::get_check(Check::Add, &arg, &one, pos);
+                quads.push(Quad::Add(arg.clone(), arg, one));
+                quads
             }
             Self::Return(x, _) => {
                 let exit_label = three_ac::get_fn_exit_lbl();
@@ -165,20 +197,33 @@ impl IRCode for Statement {
             Self::Take(x) => {
                 let arg;
                 if x.is_local() {
-                    arg = Argument::Local(format!("{x}"));
+                    arg = Argument::Local(x.unique_name());
                 } else {
                     arg = Argument::Global(format!("{x}"));
                 }
-                vec![Quad::Read(arg)]
+
+                let Ok(Kind::Variable(t)) = x.get_kind() else {
+                    unreachable!()
+                };
+
+                let pos = x.source_position();
+                let message = three_ac::add_string(&format!("runtime error at {pos}: "));
+
+                match t.unwrap_primitive() {
+                    Some((Primitive::Bool, _)) => vec![Quad::ReadBool(arg, message)],
+                    Some((Primitive::Int, _)) => vec![Quad::ReadInt(arg, message)],
+                    _ => unreachable!(),
+                }
             }
-            Self::VariableDeclaration(Declaration::Variable(VariableDeclaration {
-                name,
-                t: _,
-                assignment,
-            })) => {
-                let Some(x) = assignment else { return vec![] };
+            Self::VariableDeclaration(Declaration::Variable(declaration)) => {
+                let Some(x) = &declaration.assignment else {
+                    return vec![];
+                };
                 let (mut quads, arg) = x.get_ir_code();
-                quads.push(Quad::Assignment(Argument::Local(format!("{name}")), arg));
+                quads.push(Quad::Assignment(
+                    Argument::Local(declaration.unique_name()),
+                    arg,
+                ));
                 quads
             }
             Self::While(condition, body) => {
@@ -277,7 +322,10 @@ fn check_take(x: &Location) -> anyhow::Result<()> {
     match x.get_kind()? {
         Kind::Class => err!("FATAL {pos}: Attempt to assign user input to class"),
         Kind::Function => err!("FATAL {pos}: Attempt to assign user input to function"),
-        _ => Ok(()),
+        Kind::Variable(t) => match t.unwrap_primitive() {
+            Some((Primitive::Bool | Primitive::Int, _)) => Ok(()),
+            _ => err!("FATAL {pos}: Attempt to assign user input to {t}"),
+        },
     }
 }
 
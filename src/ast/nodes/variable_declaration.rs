@@ -9,6 +9,7 @@ pub struct VariableDeclaration {
     pub name: Id,
     pub t: Type,
     pub assignment: Option<Expression>,
+    pub unique_name: Option<String>,
 }
 
 impl TypeAnalysis for VariableDeclaration {
@@ -34,14 +35,16 @@ impl TypeAnalysis for VariableDeclaration {
 }
 
 impl VariableDeclaration {
-    fn exit_class(&self, symbol_table: &mut SymbolTable) -> anyhow::Result<()> {
+    fn exit_class(&mut self, symbol_table: &mut SymbolTable) -> anyhow::Result<()> {
         let pos = self.name.source_position();
 
         match symbol_table.link(&format!("{}", &self.t), self.t.source_position()) {
             Ok(entry) => match entry.as_ref() {
                 Class(_) => {
                     let entry = Variable(self.t.clone());
-                    symbol_table.add(&self.name.name, entry, self.name.source_position())
+                    symbol_table.add(&self.name.name, entry, self.name.source_position())?;
+                    self.set_unique_name(symbol_table);
+                    Ok(())
                 }
                 _ => {
                     err!("FATAL {pos}: Invalid type in declaration")
@@ -54,7 +57,7 @@ impl VariableDeclaration {
     }
 
     fn exit_primitive(
-        &self,
+        &mut self,
         symbol_table: &mut SymbolTable,
         t: &Primitive,
         pos: SourcePositionData,
@@ -66,10 +69,24 @@ impl VariableDeclaration {
             }
             _ => {
                 let entry = Variable(self.t.clone());
-                symbol_table.add(&self.name.name, entry, pos)
+                symbol_table.add(&self.name.name, entry, pos)?;
+                self.set_unique_name(symbol_table);
+                Ok(())
             }
         }
     }
+
+    fn set_unique_name(&mut self, symbol_table: &SymbolTable) {
+        self.unique_name = symbol_table.get_unique_name(&self.name.name);
+    }
+
+    /// Gets the unique name given to this declaration during name analysis
+    pub fn unique_name(&self) -> String {
+        match &self.unique_name {
+            Some(name) => name.clone(),
+            None => self.name.name.clone(),
+        }
+    }
 }
 
 impl std::fmt::Display for VariableDeclaration {
@@ -86,14 +103,15 @@ impl std::fmt::Display for VariableDeclaration {
 impl IRCode for VariableDeclaration {
     fn get_ir_code(&self) -> Vec<Quad> {
         let name = self.name.name.clone();
-        let global_name = format!("glb_{name}");
-        three_ac::add_global(&global_name);
+        three_ac::add_global(&name, &self.t);
 
         let Some(assignment) = &self.assignment else {
             return Vec::new();
         };
 
-        let (mut code, arg) = assignment.get_ir_code();
+        let mut code = three_ac::get_loc(Some(self.name.source_position()));
+        let (mut assignment, arg) = assignment.get_ir_code();
+        code.append(&mut assignment);
         code.push(Quad::Assignment(three_ac::Argument::Global(name), arg));
 
         code
@@ -16,12 +16,20 @@ type Stack<T> = Vec<T>;
 #[derive(Debug)]
 pub struct SymbolTable {
     table: Stack<Rc<RefCell<Scope>>>,
+    /// Unique names given to the symbols of each scope, kept in sync with `table`
+    unique_names: Stack<HashMap<String, String>>,
+    /// How many times each name has been declared in the current function
+    declaration_counts: HashMap<String, usize>,
 }
 impl SymbolTable {
     pub fn new() -> Self {
         let mut table = Vec::new();
         table.push(Rc::new(RefCell::new(HashMap::new())));
-        Self { table }
+        Self {
+            table,
+            unique_names: vec![HashMap::new()],
+            declaration_counts: HashMap::new(),
+        }
     }
 
     /// Adds a newly declared symbol to the table
@@ -49,6 +57,19 @@ impl SymbolTable {
             .unwrap()
             .insert(name.clone(), Rc::new(entry));
 
+        // Shadowing declarations in the same function get a unique name like x.1
+        let count = self.declaration_counts.entry(name.clone()).or_insert(0);
+        let unique_name = match *count {
+            0 => name.clone(),
+            n => format!("{name}.{n}"),
+        };
+        *count += 1;
+
+        self.unique_names
+            .last_mut()
+            .unwrap()
+            .insert(name.clone(), unique_name);
+
         Ok(())
     }
 
@@ -57,18 +78,27 @@ impl SymbolTable {
         let entry = Entry::Class(scope.clone());
         self.add(&id.name, entry, id.source_position())?;
         self.table.push(scope);
+        self.unique_names.push(HashMap::new());
         Ok(())
     }
 
+    /// Called when entering a function, starts a new scope and resets unique naming
+    pub fn enter_function(&mut self) {
+        self.declaration_counts = HashMap::new();
+        self.enter_scope();
+    }
+
     /// Called when entering a new scope
     pub fn enter_scope(&mut self) {
         let scope = Rc::new(RefCell::new(HashMap::new()));
         self.table.push(scope);
+        self.unique_names.push(HashMap::new());
     }
 
     /// Called when exiting a scope
     pub fn exit_scope(&mut self) {
         self.table.pop();
+        self.unique_names.pop();
     }
 
     pub fn get_class_member(
@@ -120,6 +150,14 @@ impl SymbolTable {
             .any(|scope| scope.borrow().get(name).is_some())
     }
 
+    /// Gets the unique name of the declaration this symbol currently refers to
+    pub fn get_unique_name(&self, name: &String) -> Option<String> {
+        self.unique_names
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
+    }
+
     /// Gets a link to the symbol table entry for this symbol
     pub fn link(&self, name: &String, pos: SourcePositionData) -> Result<Rc<Entry>> {
         let scope = self
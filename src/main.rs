@@ -4,14 +4,21 @@
 
 extern crate test;
 
-use std::{fs::File, io::Write};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
 
 #[allow(unused)]
 use test::Bencher;
 
 use anyhow::Result;
-use clap::Parser;
-use x64::X64Target;
+use clap::{Parser, Subcommand, ValueEnum};
+use three_ac::{
+    passes::{self, Pass},
+    Cfg, IrProgram, SsaFunction,
+};
+use x64::{runtime::Runtime, X64Target};
 
 pub mod ast;
 pub mod source_position;
@@ -22,9 +29,14 @@ pub mod x64;
 #[allow(non_snake_case)]
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    /// File to compile
-    input_file: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// File to compile, or 3AC as printed by -a if it ends in .3ac
+    #[arg(required = true)]
+    input_file: Option<String>,
 
     /// Parse
     #[arg(short, long)]
@@ -46,63 +58,307 @@ pub struct Args {
     #[arg(short, long)]
     ac3_IR_generation: Option<String>,
 
-    /// Generate x64 assembly
+    /// Generate x64 assembly, or name the executable built with --emit=exe
     #[arg(short, long)]
     output_assembly: Option<String>,
+
+    #[command(flatten)]
+    optimization: Optimization,
+
+    /// Emit an extra kind of output next to the input file
+    #[arg(long, value_enum)]
+    emit: Option<Emit>,
+
+    /// Compiler driver that assembles and links executables, instead of $CC or cc
+    #[arg(long)]
+    linker: Option<String>,
+
+    /// Keep the assembly an executable was built from, as <executable>.s
+    #[arg(long)]
+    keep_asm: bool,
+
+    /// Link a position independent executable, which is the default
+    #[arg(long, overrides_with = "no_pie")]
+    pie: bool,
+
+    /// Link an executable loaded at a fixed address
+    #[arg(long, overrides_with = "pie")]
+    no_pie: bool,
+
+    /// What the generated code does input and output with
+    #[arg(long, value_enum, default_value_t = Runtime::Libc)]
+    runtime: Runtime,
+
+    /// Fail with the source position on division by zero and integer overflow
+    #[arg(long)]
+    runtime_checks: bool,
+
+    /// Have runtime errors print the calls that led to them
+    #[arg(long)]
+    backtrace: bool,
+
+    /// Emit debug information, so debuggers can step through the source
+    #[arg(short = 'g')]
+    debug_info: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a program without assembling it
+    Run(Run),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Run {
+    /// Program to run, or 3AC as printed by -a if it ends in .3ac
+    input_file: String,
+
+    #[command(flatten)]
+    mode: RunMode,
+
+    #[command(flatten)]
+    optimization: Optimization,
+
+    /// Fail with the source position on division by zero and integer overflow
+    #[arg(long)]
+    runtime_checks: bool,
+
+    /// Have runtime errors print the calls that led to them
+    #[arg(long)]
+    backtrace: bool,
+}
+
+/// How to run a program
+#[derive(clap::Args, Debug)]
+#[group(required = true, multiple = false)]
+pub struct RunMode {
+    /// Interpret the program's 3AC
+    #[arg(long)]
+    ir: bool,
+
+    /// Walk the program's syntax tree, which is the reference for what it should do
+    #[arg(long)]
+    interp: bool,
+}
+
+/// Stack size for the AST interpreter's thread
+const INTERPRETER_STACK_SIZE: usize = 1 << 30;
+
+#[derive(clap::Args, Debug)]
+pub struct Optimization {
+    /// Optimization level, where a bare -O means -O2
+    #[arg(
+        short = 'O',
+        num_args = 0..=1,
+        default_value_t = 0,
+        default_missing_value = "2",
+        value_parser = clap::value_parser!(u8).range(0..=2)
+    )]
+    optimize: u8,
+
+    /// Run these passes in order instead of the optimization level's pipeline
+    #[arg(long, value_enum, value_delimiter = ',')]
+    passes: Option<Vec<Pass>>,
+
+    /// Print the IR to stderr after each run of a pass
+    #[arg(long, value_enum)]
+    print_after: Option<Pass>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Emit {
+    /// A Graphviz control flow graph for each function, named <input>.<function>.dot
+    CfgDot,
+    /// The program's functions in SSA form, named <input>.ssa
+    Ssa,
+    /// An executable, named by -o or after the input
+    Exe,
 }
 
 fn main() -> Result<()> {
     // Get arguments
     let args = Args::parse();
+    if let Some(Command::Run(run)) = &args.command {
+        return run_program(run);
+    }
 
     // Read file
-    let path = &args.input_file;
-    let contents = std::fs::read_to_string(path)? + "\n";
-    source_position::set_document(&contents);
+    let path = args.input_file.as_ref().unwrap();
+    let contents = read_document(path)?;
 
-    // Build AST
-    let ast = ast::build(&contents, &args)?;
+    three_ac::set_backtraces(args.backtrace);
+    three_ac::set_debug_file(args.debug_info.then(|| path.clone()));
+    let mut program = if path.ends_with(".3ac") {
+        three_ac::parse(&contents)?
+    } else {
+        // Build AST
+        let ast = ast::build(&contents, &args)?;
+
+        if args.ac3_IR_generation.is_none() && args.output_assembly.is_none() && args.emit.is_none()
+        {
+            return Ok(());
+        }
+        three_ac::set_runtime_checks(args.runtime_checks);
+        three_ac::generate(&ast)
+    };
+    optimize(&mut program, &args.optimization)?;
+    x64::runtime::set_runtime(args.runtime);
 
     // Output IR code
-    if let Some(_output_path) = &args.ac3_IR_generation {
-        let ir_code = three_ac::generate(&ast);
+    if let Some(output_path) = &args.ac3_IR_generation {
+        let mut file = File::create(output_path)?;
+        file.write_all(format!("{program}").as_bytes())?;
+    }
+
+    let stem = path
+        .strip_suffix(".dm")
+        .or(path.strip_suffix(".3ac"))
+        .unwrap_or(path);
 
-        let mut string = String::new();
-        for line in ir_code {
-            string = format!("{string}{line}");
+    // Output assembly code, or an executable built from it
+    if args.emit == Some(Emit::Exe) {
+        let exe_path = args.output_assembly.as_deref().unwrap_or(stem);
+        if exe_path == path {
+            return err!("FATAL: Building {exe_path} would overwrite the source, use -o to name the executable");
         }
-        let mut file = File::create(_output_path)?;
-        file.write_all(string.as_bytes())?;
+        let asm_path = format!("{exe_path}.s");
+
+        let mut file = File::create(&asm_path)?;
+        file.write_all(program.compile_x64().as_bytes())?;
+        link(&asm_path, exe_path, args.linker.as_deref(), !args.no_pie)?;
+
+        if !args.keep_asm {
+            std::fs::remove_file(&asm_path)?;
+        }
+    } else if let Some(output_path) = &args.output_assembly {
+        let mut file = File::create(output_path)?;
+        file.write_all(program.compile_x64().as_bytes())?;
     }
 
-    // Output assembly code
-    if let Some(_output_path) = &args.output_assembly {
-        let ir_code = three_ac::generate(&ast);
+    // Output control flow graphs
+    if args.emit == Some(Emit::CfgDot) {
+        for function in &program.functions {
+            let cfg = Cfg::new(function);
+            let mut file = File::create(format!("{stem}.{}.dot", function.name))?;
+            file.write_all(cfg.to_dot().as_bytes())?;
+        }
+    }
+
+    // Output SSA form
+    if args.emit == Some(Emit::Ssa) {
+        let mut file = File::create(format!("{stem}.ssa"))?;
 
-        let mut string = String::new();
-        for line in ir_code {
-            let asm = line.compile_x64();
-            string = format!("{string}{asm}");
+        for function in &program.functions {
+            let ssa = SsaFunction::new(function);
+            file.write_all(format!("{ssa}").as_bytes())?;
         }
-        let mut file = File::create(_output_path)?;
-        file.write_all(string.as_bytes())?;
     }
 
     Ok(())
 }
 
+/// Reads a source file, which source positions then refer to
+fn read_document(path: &str) -> Result<String> {
+    let contents = std::fs::read_to_string(path)? + "\n";
+    source_position::set_document(&contents);
+    Ok(contents)
+}
+
+fn optimize(program: &mut IrProgram, optimization: &Optimization) -> Result<()> {
+    let passes = match &optimization.passes {
+        Some(passes) => passes.clone(),
+        None => passes::get_pipeline(optimization.optimize),
+    };
+
+    passes::optimize(program, &passes, optimization.print_after)
+}
+
+/// Assembles and links with the system toolchain. On failure the assembly is
+/// left behind to look at.
+fn link(asm_path: &str, exe_path: &str, linker: Option<&str>, pie: bool) -> Result<()> {
+    let linker = match linker {
+        Some(linker) => linker.to_string(),
+        None => std::env::var("CC").unwrap_or("cc".to_string()),
+    };
+
+    // The assembly is position independent, so it links either way
+    let status = std::process::Command::new(&linker)
+        .args(x64::runtime::link_flags(pie))
+        .args([asm_path, "-o", exe_path])
+        .status();
+
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => err!("FATAL: {linker} failed to build {exe_path} from {asm_path} ({status})"),
+        Err(e) => err!("FATAL: Could not run {linker} to build {exe_path}: {e}"),
+    }
+}
+
+fn run_program(run: &Run) -> Result<()> {
+    let path = run.input_file.clone();
+    let contents = read_document(&path)?;
+
+    let (runtime_checks, backtrace) = (run.runtime_checks, run.backtrace);
+    let code = if run.mode.interp {
+        // Recursion in the program is recursion in the interpreter, so give it room
+        let interpreter = std::thread::Builder::new()
+            .stack_size(INTERPRETER_STACK_SIZE)
+            .spawn(move || {
+                let args = Args::parse_from(["dmc", &path, "--check-types"]);
+                let ast = ast::build(&contents, &args)?;
+
+                let input = std::io::stdin().lock();
+                let output = BufWriter::new(std::io::stdout().lock());
+                ast::interpret(&ast, runtime_checks, backtrace, input, output)
+            })?;
+
+        interpreter.join().unwrap()?
+    } else {
+        three_ac::set_backtraces(backtrace);
+        let mut program = if path.ends_with(".3ac") {
+            three_ac::parse(&contents)?
+        } else {
+            let args = Args::parse_from(["dmc", &path, "--check-types"]);
+            three_ac::set_runtime_checks(runtime_checks);
+            three_ac::generate(&ast::build(&contents, &args)?)
+        };
+        optimize(&mut program, &run.optimization)?;
+
+        let input = std::io::stdin().lock();
+        let output = BufWriter::new(std::io::stdout().lock());
+        three_ac::interpret(&program, input, output)?
+    };
+
+    std::process::exit(code)
+}
+
 #[bench]
 fn parser_benchmark(b: &mut Bencher) {
     let args = Args {
-        input_file: "test.dm".to_string(),
+        command: None,
+        input_file: Some("test.dm".to_string()),
         parse: true,
         unparse: None,
         named_unparse: None,
         check_types: true,
         ac3_IR_generation: None,
         output_assembly: Some("output.s".to_string()),
+        optimization: Optimization {
+            optimize: 0,
+            passes: None,
+            print_after: None,
+        },
+        emit: None,
+        linker: None,
+        keep_asm: false,
+        pie: false,
+        no_pie: false,
+        runtime: Runtime::Libc,
+        runtime_checks: false,
+        backtrace: false,
+        debug_info: false,
     };
-    let path = &args.input_file;
+    let path = args.input_file.as_ref().unwrap();
     let contents = std::fs::read_to_string(path).unwrap() + "\n";
     source_position::set_document(&contents);
 
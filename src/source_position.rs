@@ -44,6 +44,13 @@ impl Display for SourcePositionData {
     }
 }
 
+impl SourcePositionData {
+    /// Line and column the range starts at
+    pub fn start(&self) -> Result<(usize, usize)> {
+        Ok((get_line_number(self.s)?, get_column(self.s)?))
+    }
+}
+
 /// Sets the source code document being worked on
 pub fn set_document(contents: &String) {
     unsafe { SOURCE_DOCUMENT = Some(contents.clone()) }
@@ -0,0 +1,285 @@
+//! # Control Flow Graph
+//! Splits a function's quads into basic blocks linked by control flow edges
+
+use std::collections::HashMap;
+
+use super::{IrFunction, Quad};
+
+/// A straight line run of quads, only entered at the top and left at the bottom
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub quads: Vec<Quad>,
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+
+/// Basic blocks of a function, in their original order. Block 0 is the entry.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub name: String,
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl BasicBlock {
+    /// The label at the top of this block, if anything jumps here by name
+    pub fn label(&self) -> Option<&String> {
+        self.quads.first().and_then(|quad| quad.label())
+    }
+}
+
+impl Cfg {
+    pub fn new(function: &IrFunction) -> Self {
+        let mut blocks = split_blocks(&function.body);
+        let labels = label_blocks(&blocks);
+
+        for i in 0..blocks.len() {
+            let Some(last) = blocks[i].quads.last() else {
+                continue;
+            };
+
+            let mut successors = Vec::new();
+            if last.falls_through() && i + 1 < blocks.len() {
+                successors.push(i + 1);
+            }
+
+            if let Some(&target) = last.jump_target().and_then(|label| labels.get(label)) {
+                if !successors.contains(&target) {
+                    successors.push(target);
+                }
+            }
+
+            for successor in &successors {
+                blocks[*successor].predecessors.push(i);
+            }
+            blocks[i].successors = successors;
+        }
+
+        Self {
+            name: function.name.clone(),
+            blocks,
+        }
+    }
+
+    /// Flattens the graph back into a list of quads
+    pub fn to_quads(&self) -> Vec<Quad> {
+        self.blocks
+            .iter()
+            .flat_map(|block| block.quads.clone())
+            .collect()
+    }
+
+    /// Blocks in reverse postorder from the entry, unreachable blocks are left out
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+
+        if !self.blocks.is_empty() {
+            self.postorder(0, &mut visited, &mut order);
+        }
+
+        order.reverse();
+        order
+    }
+
+    fn postorder(&self, block: usize, visited: &mut Vec<bool>, order: &mut Vec<usize>) {
+        visited[block] = true;
+
+        for successor in &self.blocks[block].successors {
+            if !visited[*successor] {
+                self.postorder(*successor, visited, order);
+            }
+        }
+
+        order.push(block);
+    }
+
+    /// Immediate dominator of each block, using the Cooper, Harvey, and Kennedy
+    /// algorithm. The entry is its own immediate dominator, and unreachable
+    /// blocks have none.
+    pub fn immediate_dominators(&self) -> Vec<Option<usize>> {
+        let order = self.reverse_postorder();
+        let mut position = vec![usize::MAX; self.blocks.len()];
+        for (i, block) in order.iter().enumerate() {
+            position[*block] = i;
+        }
+
+        let mut idom = vec![None; self.blocks.len()];
+        if order.is_empty() {
+            return idom;
+        }
+        idom[0] = Some(0);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for block in order.iter().skip(1) {
+                let mut new_idom = None;
+
+                for predecessor in &self.blocks[*block].predecessors {
+                    if idom[*predecessor].is_none() {
+                        continue;
+                    }
+
+                    new_idom = match new_idom {
+                        None => Some(*predecessor),
+                        Some(other) => Some(intersect(&idom, &position, *predecessor, other)),
+                    };
+                }
+
+                if idom[*block] != new_idom {
+                    idom[*block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        idom
+    }
+
+    /// Dominance frontier of each block
+    pub fn dominance_frontiers(&self, idom: &[Option<usize>]) -> Vec<Vec<usize>> {
+        let mut frontiers = vec![Vec::new(); self.blocks.len()];
+
+        for (block, data) in self.blocks.iter().enumerate() {
+            let predecessors: Vec<usize> = data
+                .predecessors
+                .iter()
+                .filter(|p| idom[**p].is_some())
+                .copied()
+                .collect();
+
+            if predecessors.len() < 2 || idom[block].is_none() {
+                continue;
+            }
+
+            for predecessor in predecessors {
+                let mut runner = predecessor;
+
+                while Some(runner) != idom[block] {
+                    if !frontiers[runner].contains(&block) {
+                        frontiers[runner].push(block);
+                    }
+                    runner = idom[runner].unwrap();
+                }
+            }
+        }
+
+        frontiers
+    }
+
+    /// Children of each block in the dominator tree
+    pub fn dominator_tree(&self, idom: &[Option<usize>]) -> Vec<Vec<usize>> {
+        let mut children = vec![Vec::new(); self.blocks.len()];
+
+        for (block, parent) in idom.iter().enumerate() {
+            match parent {
+                Some(parent) if *parent != block => children[*parent].push(block),
+                _ => (),
+            }
+        }
+
+        children
+    }
+
+    /// Renders the graph in Graphviz's dot format
+    pub fn to_dot(&self) -> String {
+        let mut string = format!(
+            "digraph \"{}\" {{\n\
+			node [shape=box, fontname=\"monospace\"];\n",
+            escape_dot(&self.name)
+        );
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut label = format!("B{i}\\l");
+            for quad in &block.quads {
+                label = format!("{label}{}\\l", escape_dot(format!("{quad}").trim_end()));
+            }
+
+            string = format!("{string}b{i} [label=\"{label}\"];\n");
+        }
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            let branch = block.quads.last().and_then(|quad| match quad {
+                Quad::Ifz(_, label) => Some(label),
+                _ => None,
+            });
+
+            for successor in &block.successors {
+                match branch {
+                    Some(label) if self.blocks[*successor].label() == Some(label) => {
+                        string = format!("{string}b{i} -> b{successor} [label=\"ifz\"];\n")
+                    }
+                    _ => string = format!("{string}b{i} -> b{successor};\n"),
+                }
+            }
+        }
+
+        format!("{string}}}\n")
+    }
+}
+
+/// Walks two blocks up the dominator tree until they meet
+fn intersect(idom: &[Option<usize>], position: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while position[a] > position[b] {
+            a = idom[a].unwrap();
+        }
+        while position[b] > position[a] {
+            b = idom[b].unwrap();
+        }
+    }
+
+    a
+}
+
+/// Cuts quads into blocks at labels and after jumps
+fn split_blocks(quads: &[Quad]) -> Vec<BasicBlock> {
+    let mut blocks = Vec::new();
+    let mut current = Vec::new();
+
+    for quad in quads {
+        if quad.label().is_some() && !current.is_empty() {
+            blocks.push(new_block(current));
+            current = Vec::new();
+        }
+
+        current.push(quad.clone());
+
+        if quad.ends_block() {
+            blocks.push(new_block(current));
+            current = Vec::new();
+        }
+    }
+
+    if !current.is_empty() {
+        blocks.push(new_block(current));
+    }
+
+    blocks
+}
+
+fn new_block(quads: Vec<Quad>) -> BasicBlock {
+    BasicBlock {
+        quads,
+        successors: Vec::new(),
+        predecessors: Vec::new(),
+    }
+}
+
+/// Maps each label to the block it starts
+fn label_blocks(blocks: &[BasicBlock]) -> HashMap<String, usize> {
+    let mut labels = HashMap::new();
+
+    for (i, block) in blocks.iter().enumerate() {
+        if let Some(label) = block.label() {
+            labels.insert(label.clone(), i);
+        }
+    }
+
+    labels
+}
+
+fn escape_dot(string: &str) -> String {
+    string.replace('\\', "\\\\").replace('"', "\\\"")
+}
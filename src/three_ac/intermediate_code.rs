@@ -1,21 +1,119 @@
 //! It only occurred to me that I shouldn't just output text after a few hours of
 //! work. That's a problem for future me. Screw that guy.
 
-use super::{IRCode, Quad};
-use crate::ast::Declaration;
+use super::{
+    Argument, Check, Global, IRCode, IrFunction, IrProgram, Quad, StringConstant, INIT_FN,
+};
+use crate::{
+    ast::{Declaration, Type},
+    source_position::SourcePositionData,
+};
 
 static mut LBL_COUNTER: usize = 0;
 static mut STR_COUNTER: usize = 0;
 static mut TMP_COUNTER: usize = 0;
 static mut FN_EXIT_LBL: String = String::new();
-pub static mut GLOBALS: Vec<String> = Vec::new();
+static mut GLOBALS: Vec<Global> = Vec::new();
+static mut STRINGS: Vec<StringConstant> = Vec::new();
+static mut RUNTIME_CHECKS: bool = false;
+static mut BACKTRACES: bool = false;
+static mut DEBUG_FILE: Option<String> = None;
+static mut DIVISIONS: Vec<(Option<String>, String, SourcePositionData)> = Vec::new();
+
+/// Has arithmetic that can fail checked at runtime
+pub fn set_runtime_checks(runtime_checks: bool) {
+    unsafe { RUNTIME_CHECKS = runtime_checks }
+}
+
+pub fn add_global(name: &str, t: &Type) {
+    let global = Global {
+        name: name.to_string(),
+        t: t.clone(),
+    };
+    unsafe { GLOBALS.push(global) }
+}
+
+/// Adds a string literal to the data section, returning its label
+pub fn add_string(value: &str) -> String {
+    let label = get_str();
+    let string = StringConstant {
+        label: label.clone(),
+        value: value.to_string(),
+    };
+    unsafe { STRINGS.push(string) }
+    label
+}
+
+/// Has runtime errors also print the calls that led to them
+pub fn set_backtraces(backtraces: bool) {
+    unsafe { BACKTRACES = backtraces }
+}
+
+pub fn get_backtraces() -> bool {
+    unsafe { BACKTRACES }
+}
+
+/// Has the program carry debug information about where its code came from in
+/// the source file, or not if it's None
+pub fn set_debug_file(path: Option<String>) {
+    unsafe { DEBUG_FILE = path }
+}
+
+pub fn get_debug_file() -> Option<String> {
+    unsafe { DEBUG_FILE.clone() }
+}
+
+/// Marks the quads after it as coming from a source position, if the program
+/// carries debug information
+pub fn get_loc(pos: Option<SourcePositionData>) -> Vec<Quad> {
+    match (pos, get_debug_file().is_some()) {
+        (Some(pos), true) => vec![Quad::Loc(pos)],
+        _ => Vec::new(),
+    }
+}
+
+/// Checks an operation on x and y before it's done, if runtime checks are on,
+/// failing with the source position it came from
+pub fn get_check(check: Check, x: &Argument, y: &Argument, pos: SourcePositionData) -> Vec<Quad> {
+    if unsafe { !RUNTIME_CHECKS } {
+        return Vec::new();
+    }
 
-pub fn add_global(str: &String) {
-    unsafe { GLOBALS.push(str.clone()) }
+    let message = add_string(&format!("runtime error at {pos}: "));
+    vec![Quad::Check(check, x.clone(), y.clone(), message)]
 }
 
-pub fn generate(ast: &Vec<Declaration>) -> Vec<Quad> {
-    let mut quads = Vec::new();
+/// Remembers where the division into `location` came from, so dividing by zero
+/// can be reported there. Temps made after generation are only unique within
+/// their function, so their divisions are remembered for it.
+pub fn add_division(function: Option<&str>, location: &Argument, pos: SourcePositionData) {
+    if let Argument::Local(name) = location {
+        let function = function.map(str::to_string);
+        unsafe { (*std::ptr::addr_of_mut!(DIVISIONS)).push((function, name.clone(), pos)) }
+    }
+}
+
+pub fn get_division(function: &str, location: &Argument) -> Option<SourcePositionData> {
+    let name = location.local()?;
+    let divisions = unsafe { &*std::ptr::addr_of!(DIVISIONS) };
+    let find = |f: Option<&str>| {
+        divisions
+            .iter()
+            .find(|(g, n, _)| g.as_deref() == f && n == name)
+            .map(|(_, _, pos)| *pos)
+    };
+
+    find(Some(function)).or_else(|| find(None))
+}
+
+pub fn generate(ast: &Vec<Declaration>) -> IrProgram {
+    unsafe {
+        GLOBALS = Vec::new();
+        STRINGS = Vec::new();
+        DIVISIONS = Vec::new();
+    }
+
+    let mut functions = Vec::new();
 
     // Hit function declarations
     for declaration in ast {
@@ -23,36 +121,45 @@ pub fn generate(ast: &Vec<Declaration>) -> Vec<Quad> {
             continue;
         };
 
-        quads.append(&mut function.get_ir_code());
+        functions.push(function.get_ir_function());
     }
 
-    // Kick off main like _start should
-    quads.push(Quad::Label("main".to_string()));
+    // Run global declarations in their own function before main
+    let exit_label = get_new_fn_exit_lbl();
+    let start_tmps = get_tmp_counter();
+    let mut body = vec![Quad::Enter(INIT_FN.to_string())];
 
-    // Run global declarations
     for declaration in ast {
         let Declaration::Variable(var) = declaration else {
             continue;
         };
 
-        quads.append(&mut var.get_ir_code());
+        body.append(&mut var.get_ir_code());
     }
 
-    quads.push(Quad::Goto("fn_main\n".to_string()));
+    body.push(Quad::Leave(exit_label, INIT_FN.to_string()));
 
-    let mut globals = vec![get_globals()];
-    globals.append(&mut quads);
-    globals
+    functions.push(IrFunction {
+        name: INIT_FN.to_string(),
+        formals: Vec::new(),
+        locals: Vec::new(),
+        temps: start_tmps..get_tmp_counter(),
+        body,
+    });
+
+    unsafe {
+        IrProgram {
+            globals: GLOBALS.clone(),
+            strings: STRINGS.clone(),
+            functions,
+        }
+    }
 }
 
 pub fn get_fn_exit_lbl() -> String {
     unsafe { FN_EXIT_LBL.clone() }
 }
 
-fn get_globals() -> Quad {
-    unsafe { Quad::Globals(GLOBALS.clone()) }
-}
-
 pub fn get_last_tmp() -> String {
     let ctr;
 
@@ -75,6 +182,15 @@ pub fn get_lbl() -> String {
     format!("lbl_{ctr}")
 }
 
+/// Makes sure a label that's already in use is never handed out again
+pub fn claim_lbl(label: &str) {
+    let Some(Ok(ctr)) = label.strip_prefix("lbl_").map(str::parse::<usize>) else {
+        return;
+    };
+
+    unsafe { LBL_COUNTER = LBL_COUNTER.max(ctr + 1) }
+}
+
 pub fn get_new_fn_exit_lbl() -> String {
     let lbl = get_lbl();
     unsafe { FN_EXIT_LBL = lbl.clone() }
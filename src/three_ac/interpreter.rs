@@ -0,0 +1,394 @@
+//! # IR Interpreter
+//! Runs a program's quads directly, so programs and passes can be checked
+//! without an assembler. Behaves like the generated x64: values are 64 bit,
+//! `take` reads a line and fails on anything but an int or bool, and main's
+//! return value is the exit code.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    num::IntErrorKind,
+};
+
+use anyhow::Result;
+
+use super::{intermediate_code, Argument, Check, IrFunction, IrProgram, Quad, INIT_FN};
+use crate::err;
+
+/// A function call in progress
+struct Frame<'a> {
+    function: &'a IrFunction,
+    labels: HashMap<&'a String, usize>,
+    /// Index of the next quad to run
+    next: usize,
+    args: HashMap<usize, u64>,
+    variables: HashMap<String, u64>,
+    /// Where the function's latest call is made from, for backtraces
+    site: Option<&'a String>,
+}
+
+struct Interpreter<'a, R: BufRead, W: Write> {
+    program: &'a IrProgram,
+    globals: HashMap<String, u64>,
+    strings: HashMap<&'a String, String>,
+    frames: Vec<Frame<'a>>,
+    /// Arguments set for the next call
+    args: HashMap<usize, u64>,
+    /// Value of the last return, which is zero when a function falls into its leave
+    ret: u64,
+    /// Exit code if the program exits before main returns
+    exit_code: i32,
+    input: R,
+    output: W,
+}
+
+/// Runs the global initializers and then main, returning the exit code
+pub fn interpret(program: &IrProgram, input: impl BufRead, output: impl Write) -> Result<i32> {
+    let mut interpreter = Interpreter {
+        program,
+        globals: HashMap::new(),
+        strings: HashMap::new(),
+        frames: Vec::new(),
+        args: HashMap::new(),
+        ret: 0,
+        exit_code: 0,
+        input,
+        output,
+    };
+
+    for string in &program.strings {
+        interpreter
+            .strings
+            .insert(&string.label, unescape(&string.value));
+    }
+
+    let code = match interpreter.call(INIT_FN)? {
+        Some(_) => interpreter.call("main")?.map(|ret| ret as u8 as i32),
+        None => None,
+    };
+
+    interpreter.output.flush()?;
+    Ok(code.unwrap_or(interpreter.exit_code))
+}
+
+impl<'a, R: BufRead, W: Write> Interpreter<'a, R, W> {
+    /// Runs a function to completion, giving back its return value, or None if
+    /// the program exited along the way
+    fn call(&mut self, name: &str) -> Result<Option<u64>> {
+        let depth = self.frames.len();
+        self.push_frame(name)?;
+
+        while self.frames.len() > depth {
+            let frame = self.frames.last_mut().unwrap();
+            let Some(quad) = frame.function.body.get(frame.next) else {
+                return err!(
+                    "FATAL in {}: Ran past the end of the function",
+                    frame.function.name
+                );
+            };
+            frame.next += 1;
+
+            if !self.step(quad)? {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(self.ret))
+    }
+
+    fn push_frame(&mut self, name: &str) -> Result<()> {
+        let Some(function) = self.program.get_function(name) else {
+            return err!("FATAL: Call to undefined function {name}");
+        };
+
+        let mut labels = HashMap::new();
+        for (i, quad) in function.body.iter().enumerate() {
+            if let Some(label) = quad.label() {
+                labels.insert(label, i);
+            }
+        }
+
+        self.frames.push(Frame {
+            function,
+            labels,
+            next: 0,
+            args: std::mem::take(&mut self.args),
+            variables: HashMap::new(),
+            site: None,
+        });
+        Ok(())
+    }
+
+    /// Runs one quad, returning false if the program exits
+    fn step(&mut self, quad: &'a Quad) -> Result<bool> {
+        match quad {
+            Quad::Add(w, x, y) => self.binary(w, x, y, u64::wrapping_add),
+            Quad::And(w, x, y) => self.binary(w, x, y, |x, y| x & y),
+            Quad::Assignment(w, x) => self.set(w, self.get(x)),
+            Quad::Call(name) => {
+                self.push_frame(name)?;
+            }
+            Quad::CallSite(site) => self.frames.last_mut().unwrap().site = Some(site),
+            Quad::Check(check, x, y, message) => {
+                let (x, y) = (self.get(x) as i64, self.get(y) as i64);
+                let result = match check {
+                    Check::Add => x.checked_add(y),
+                    Check::Divide if y == 0 => {
+                        return self.runtime_error(message, "division by zero")
+                    }
+                    Check::Divide => x.checked_div(y),
+                    Check::Multiply => x.checked_mul(y),
+                    Check::Subtract => x.checked_sub(y),
+                };
+                if result.is_none() {
+                    return self.runtime_error(message, "integer overflow");
+                }
+            }
+            Quad::Divide(w, x, y) => {
+                let (x, y) = (self.get(x) as i64, self.get(y) as i64);
+                if y == 0 {
+                    let name = &self.frame().function.name;
+                    return match intermediate_code::get_division(name, w) {
+                        Some(pos) => err!("FATAL {pos}: Division by zero"),
+                        None => err!("FATAL in {name}: Division by zero"),
+                    };
+                }
+                self.set(w, x.wrapping_div(y) as u64);
+            }
+            Quad::Enter(_) | Quad::Label(_) | Quad::Loc(_) => (),
+            Quad::Equals(w, x, y) => self.binary(w, x, y, |x, y| (x == y) as u64),
+            Quad::Exit => return Ok(false),
+            Quad::GetArg(n, w) => {
+                let value = self.frame().args.get(n).copied().unwrap_or(0);
+                self.set(w, value);
+            }
+            Quad::GetRet(w) => self.set(w, self.ret),
+            Quad::Goto(label) => self.jump(label)?,
+            Quad::Greater(w, x, y) => self.compare(w, x, y, |x, y| x > y),
+            Quad::GreaterEq(w, x, y) => self.compare(w, x, y, |x, y| x >= y),
+            Quad::Ifz(c, label) => {
+                if self.get(c) == 0 {
+                    self.jump(label)?;
+                }
+            }
+            Quad::Leave(_, _) => {
+                // Jumping to the leave skips over this, keeping the return value
+                self.ret = 0;
+                self.frames.pop();
+            }
+            Quad::Less(w, x, y) => self.compare(w, x, y, |x, y| x < y),
+            Quad::LessEq(w, x, y) => self.compare(w, x, y, |x, y| x <= y),
+            Quad::Multiply(w, x, y) => self.binary(w, x, y, u64::wrapping_mul),
+            Quad::Not(w, x) => self.set(w, self.get(x) ^ 1),
+            Quad::NotEq(w, x, y) => self.binary(w, x, y, |x, y| (x != y) as u64),
+            Quad::Or(w, x, y) => self.binary(w, x, y, |x, y| x | y),
+            Quad::ReadBool(w, message) | Quad::ReadInt(w, message) => {
+                let mut line = String::new();
+                self.output.flush()?;
+                self.input.read_line(&mut line)?;
+
+                let value = match quad {
+                    Quad::ReadBool(_, _) => parse_bool(&line).map(u64::from),
+                    _ => parse_int(&line).map(|value| value as u64),
+                };
+
+                match value {
+                    Ok(value) => self.set(w, value),
+                    Err(problem) => return self.runtime_error(message, problem),
+                }
+            }
+            Quad::SetArg(n, x) => {
+                self.args.insert(*n, self.get(x));
+            }
+            Quad::SetRet(x) => self.ret = self.get(x),
+            Quad::ShiftLeft(w, x, y) => self.binary(w, x, y, |x, y| x << (y & 63)),
+            Quad::ShiftRight(w, x, y) => {
+                self.binary(w, x, y, |x, y| ((x as i64) >> (y & 63)) as u64)
+            }
+            Quad::Subtract(w, x, y) => self.binary(w, x, y, u64::wrapping_sub),
+            Quad::TailCall(name) => {
+                self.frames.pop();
+                self.push_frame(name)?;
+            }
+            Quad::WriteBool(x) => {
+                let value = if self.get(x) == 0 { "false" } else { "true" };
+                write!(self.output, "{value}")?;
+            }
+            Quad::WriteInt(x) => write!(self.output, "{}", self.get(x) as i64)?,
+            Quad::WriteStr(x) => {
+                let string = match x {
+                    Argument::Global(label) => self.strings.get(label),
+                    _ => None,
+                };
+
+                match string {
+                    Some(string) => write!(self.output, "{string}")?,
+                    None => {
+                        return err!(
+                            "FATAL in {}: Invalid string {x}",
+                            self.frame().function.name
+                        )
+                    }
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Reports an error the way the runtime does, which exits the program
+    fn runtime_error(&mut self, message: &String, problem: &str) -> Result<bool> {
+        self.output.flush()?;
+
+        let Some(message) = self.strings.get(message) else {
+            let name = &self.frame().function.name;
+            return err!("FATAL in {name}: Invalid error message {message}");
+        };
+        eprintln!("{message}{problem}");
+        if super::get_backtraces() {
+            self.backtrace();
+        }
+
+        self.exit_code = 1;
+        Ok(false)
+    }
+
+    /// Writes the functions running from the innermost out, like the runtime.
+    /// The global initializers aren't a function of the program, so they're left
+    /// out, along with where they call from.
+    fn backtrace(&self) {
+        let frames: Vec<&Frame> = self
+            .frames
+            .iter()
+            .filter(|f| f.function.name != INIT_FN)
+            .collect();
+
+        for (i, frame) in frames.iter().enumerate().rev() {
+            let site = match i {
+                0 => None,
+                _ => frames[i - 1].site.and_then(|site| self.strings.get(site)),
+            };
+            eprintln!("  in {}{}", frame.function.name, site.map_or("", |s| s));
+        }
+    }
+
+    fn frame(&self) -> &Frame<'a> {
+        self.frames.last().unwrap()
+    }
+
+    fn get(&self, argument: &Argument) -> u64 {
+        match argument {
+            Argument::Literal(value) => *value,
+            Argument::Local(name) => self.frame().variables.get(name).copied().unwrap_or(0),
+            Argument::Global(name) => self.globals.get(name).copied().unwrap_or(0),
+        }
+    }
+
+    fn set(&mut self, argument: &Argument, value: u64) {
+        match argument {
+            Argument::Literal(_) => (),
+            Argument::Local(name) => {
+                let frame = self.frames.last_mut().unwrap();
+                frame.variables.insert(name.clone(), value);
+            }
+            Argument::Global(name) => {
+                self.globals.insert(name.clone(), value);
+            }
+        }
+    }
+
+    fn binary(&mut self, w: &Argument, x: &Argument, y: &Argument, f: fn(u64, u64) -> u64) {
+        self.set(w, f(self.get(x), self.get(y)));
+    }
+
+    /// Signed comparison, giving 1 or 0
+    fn compare(&mut self, w: &Argument, x: &Argument, y: &Argument, f: fn(i64, i64) -> bool) {
+        let (x, y) = (self.get(x) as i64, self.get(y) as i64);
+        self.set(w, f(x, y) as u64);
+    }
+
+    fn jump(&mut self, label: &String) -> Result<()> {
+        let frame = self.frames.last_mut().unwrap();
+        let Some(target) = frame.labels.get(label) else {
+            return err!(
+                "FATAL in {}: Jump to undefined label {label}",
+                frame.function.name
+            );
+        };
+
+        // Skip the target itself, since running a leave means falling into it
+        frame.next = target + 1;
+        if matches!(frame.function.body[*target], Quad::Leave(_, _)) {
+            self.frames.pop();
+        }
+
+        Ok(())
+    }
+}
+
+/// Longest line of input a take accepts, besides its newline. It's what fits
+/// in the runtimes' buffer along with the newline and a NUL.
+const MAX_LINE: usize = 1022;
+
+/// A line of input as an int, with whitespace around it, or what's wrong with it
+pub fn parse_int(line: &str) -> Result<i64, &'static str> {
+    match trim_space(check_line(line)?).parse() {
+        Ok(value) => Ok(value),
+        Err(e)
+            if matches!(
+                e.kind(),
+                IntErrorKind::PosOverflow | IntErrorKind::NegOverflow
+            ) =>
+        {
+            Err("input is out of range for an int")
+        }
+        Err(_) => Err("input is not an int"),
+    }
+}
+
+/// A line of input as a bool, with whitespace around it, or what's wrong with it
+pub fn parse_bool(line: &str) -> Result<bool, &'static str> {
+    match trim_space(check_line(line)?) {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err("input is not true or false"),
+    }
+}
+
+/// A line of input without its newline, unless it's too long to take
+fn check_line(line: &str) -> Result<&str, &'static str> {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    if line.len() > MAX_LINE {
+        return Err("input line is too long");
+    }
+
+    Ok(line)
+}
+
+/// Trims the same whitespace as C's isspace
+fn trim_space(line: &str) -> &str {
+    line.trim_matches(|c| matches!(c, ' ' | '\t'..='\r'))
+}
+
+/// A string literal's value, with its escapes as the assembler reads them
+pub fn unescape(value: &str) -> String {
+    let mut string = String::new();
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => string.push('\n'),
+            Some('t') => string.push('\t'),
+            Some('0') => string.push('\0'),
+            Some(c) => string.push(c),
+            None => (),
+        }
+    }
+
+    string
+}
@@ -0,0 +1,79 @@
+//! # Liveness Analysis
+//! Finds which locals hold a value that may still be read later on
+
+use std::collections::HashSet;
+
+use super::{Cfg, Quad};
+
+/// Locals live on entry to and exit from each block
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    pub live_in: Vec<HashSet<String>>,
+    pub live_out: Vec<HashSet<String>>,
+}
+
+impl Liveness {
+    pub fn new(cfg: &Cfg) -> Self {
+        let blocks = cfg.blocks.len();
+        let mut live_in = vec![HashSet::new(); blocks];
+        let mut live_out: Vec<HashSet<String>> = vec![HashSet::new(); blocks];
+
+        // Iterating in reverse postorder backwards converges quickly
+        let mut order = cfg.reverse_postorder();
+        order.reverse();
+        let unreachable = (0..blocks)
+            .filter(|b| !order.contains(b))
+            .collect::<Vec<_>>();
+        order.extend(unreachable);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for block in &order {
+                let mut out = HashSet::new();
+                for successor in &cfg.blocks[*block].successors {
+                    out.extend(live_in[*successor].iter().cloned());
+                }
+
+                let mut live = out.clone();
+                for quad in cfg.blocks[*block].quads.iter().rev() {
+                    transfer(quad, &mut live);
+                }
+
+                if live != live_in[*block] {
+                    live_in[*block] = live;
+                    changed = true;
+                }
+                live_out[*block] = out;
+            }
+        }
+
+        Self { live_in, live_out }
+    }
+
+    /// Locals live right after each quad of a block
+    pub fn live_after_quads(&self, cfg: &Cfg, block: usize) -> Vec<HashSet<String>> {
+        let quads = &cfg.blocks[block].quads;
+        let mut live = self.live_out[block].clone();
+        let mut after = vec![HashSet::new(); quads.len()];
+
+        for (i, quad) in quads.iter().enumerate().rev() {
+            after[i] = live.clone();
+            transfer(quad, &mut live);
+        }
+
+        after
+    }
+}
+
+/// Steps liveness backwards over a single quad
+pub fn transfer(quad: &Quad, live: &mut HashSet<String>) {
+    if let Some(name) = quad.def().and_then(|d| d.local()) {
+        live.remove(name);
+    }
+
+    for name in quad.uses().into_iter().filter_map(|u| u.local()) {
+        live.insert(name.clone());
+    }
+}
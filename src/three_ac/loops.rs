@@ -0,0 +1,100 @@
+//! # Loop Detection
+//! Finds the natural loops of a control flow graph from its back edges, which
+//! are edges to a block that dominates where they come from
+
+use super::Cfg;
+
+/// A loop entered only through its header
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub header: usize,
+    /// Every block in the loop, including the header, in order
+    pub blocks: Vec<usize>,
+}
+
+impl Loop {
+    pub fn contains(&self, block: usize) -> bool {
+        self.blocks.binary_search(&block).is_ok()
+    }
+
+    /// Blocks outside the loop that it can exit to
+    pub fn exits(&self, cfg: &Cfg) -> Vec<usize> {
+        let mut exits = Vec::new();
+
+        for block in &self.blocks {
+            for successor in &cfg.blocks[*block].successors {
+                if !self.contains(*successor) && !exits.contains(successor) {
+                    exits.push(*successor);
+                }
+            }
+        }
+
+        exits
+    }
+}
+
+/// Every loop in the graph, innermost first. Back edges to the same header
+/// make up a single loop.
+pub fn find_loops(cfg: &Cfg, idom: &[Option<usize>]) -> Vec<Loop> {
+    let mut loops: Vec<Loop> = Vec::new();
+
+    for (block, data) in cfg.blocks.iter().enumerate() {
+        for header in &data.successors {
+            if idom[block].is_none() || !dominates(idom, *header, block) {
+                continue;
+            }
+
+            let body = get_loop_body(cfg, idom, *header, block);
+            match loops.iter_mut().find(|l| l.header == *header) {
+                Some(existing) => {
+                    existing.blocks.extend(body);
+                    existing.blocks.sort();
+                    existing.blocks.dedup();
+                }
+                None => loops.push(Loop {
+                    header: *header,
+                    blocks: body,
+                }),
+            }
+        }
+    }
+
+    loops.sort_by_key(|l| l.blocks.len());
+    loops
+}
+
+/// Whether every path from the entry to b goes through a
+pub fn dominates(idom: &[Option<usize>], a: usize, b: usize) -> bool {
+    let mut runner = b;
+
+    loop {
+        if runner == a {
+            return true;
+        }
+
+        match idom[runner] {
+            Some(parent) if parent != runner => runner = parent,
+            _ => return false,
+        }
+    }
+}
+
+/// Reachable blocks that can get to the end of a back edge without going
+/// through the header
+fn get_loop_body(cfg: &Cfg, idom: &[Option<usize>], header: usize, tail: usize) -> Vec<usize> {
+    let mut body = vec![header];
+    let mut stack = vec![tail];
+
+    while let Some(block) = stack.pop() {
+        if body.contains(&block) {
+            continue;
+        }
+
+        body.push(block);
+        let predecessors = cfg.blocks[block].predecessors.iter();
+        stack.extend(predecessors.filter(|p| idom[**p].is_some()));
+    }
+
+    body.sort();
+    body
+}
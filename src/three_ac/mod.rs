@@ -1,20 +1,47 @@
+mod cfg;
 mod intermediate_code;
+mod interpreter;
+mod liveness;
+mod loops;
+mod parser;
+pub mod passes;
+mod program;
 mod quads;
+mod ssa;
+mod verifier;
 
+pub use cfg::{BasicBlock, Cfg};
 pub use intermediate_code::*;
-pub use quads::Quad;
+pub use interpreter::{interpret, parse_bool, parse_int, unescape};
+pub use liveness::Liveness;
+pub use loops::{dominates, find_loops, Loop};
+pub use parser::parse;
+pub use program::*;
+pub use quads::{Check, Quad};
+pub use ssa::{Phi, SsaFunction};
+pub use verifier::verify;
 
 pub trait IRCode {
     fn get_ir_code(&self) -> Vec<Quad>;
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Argument {
     Literal(u64),
     Local(String),
     Global(String),
 }
 
+impl Argument {
+    /// Name of the local variable this refers to
+    pub fn local(&self) -> Option<&String> {
+        match self {
+            Argument::Local(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
 impl std::fmt::Display for Argument {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -0,0 +1,317 @@
+//! # IR Parser
+//! Reads a program back from the text it's displayed as, so IR can be written
+//! by hand or fed from `-a` output straight into the backend
+
+use anyhow::Result;
+
+use super::{
+    intermediate_code, verify, Argument, Check, Global, IrFunction, IrProgram, Quad, StringConstant,
+};
+use crate::{
+    ast::{Id, Primitive, Type},
+    err,
+    source_position::SourcePositionData,
+};
+
+/// A non-empty line, with the byte position it starts at
+struct Line<'a> {
+    position: usize,
+    text: &'a str,
+}
+
+impl Line<'_> {
+    fn source_position(&self) -> SourcePositionData {
+        SourcePositionData {
+            s: self.position,
+            e: self.position + self.text.len(),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<IrProgram> {
+    let mut lines = Vec::new();
+    let mut position = 0;
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if !trimmed.is_empty() {
+            let indent = line.len() - line.trim_start().len();
+            lines.push(Line {
+                position: position + indent,
+                text: trimmed,
+            });
+        }
+        position += line.len();
+    }
+
+    let mut lines = lines.into_iter().peekable();
+    let mut program = IrProgram {
+        globals: Vec::new(),
+        strings: Vec::new(),
+        functions: Vec::new(),
+    };
+
+    match lines.next() {
+        Some(line) if line.text == "[BEGIN GLOBALS]" => (),
+        Some(line) => {
+            let pos = line.source_position();
+            return err!("FATAL {pos}: Expected [BEGIN GLOBALS]");
+        }
+        None => return err!("FATAL: Expected [BEGIN GLOBALS]"),
+    }
+
+    loop {
+        let Some(line) = lines.next() else {
+            return err!("FATAL: Expected [End GLOBALS]");
+        };
+
+        if line.text == "[End GLOBALS]" {
+            break;
+        }
+
+        if line.text.starts_with("fn_") && !line.text.contains(' ') {
+            continue;
+        }
+
+        if let Some(global) = parse_global(line.text) {
+            program.globals.push(global);
+        } else if let Some(string) = parse_string(line.text) {
+            program.strings.push(string);
+        } else {
+            let pos = line.source_position();
+            return err!("FATAL {pos}: Invalid global");
+        }
+    }
+
+    while let Some(line) = lines.next() {
+        let Some(name) = enclosed(line.text, "[BEGIN ", " LOCALS]") else {
+            let pos = line.source_position();
+            return err!("FATAL {pos}: Expected [BEGIN <function> LOCALS]");
+        };
+
+        let mut function = IrFunction {
+            name: name.to_string(),
+            formals: Vec::new(),
+            locals: Vec::new(),
+            temps: 0..0,
+            body: Vec::new(),
+        };
+        let mut temps = Vec::new();
+
+        let end = format!("[END {name} LOCALS]");
+        loop {
+            let Some(line) = lines.next() else {
+                return err!("FATAL: Expected {end}");
+            };
+
+            if line.text == end {
+                break;
+            }
+
+            let pos = line.source_position();
+            match line.text.split_once(' ') {
+                Some((name, "(formal arg of 8 bytes)")) => function.formals.push(name.to_string()),
+                Some((name, "(local var of 8 bytes)")) => function.locals.push(name.to_string()),
+                Some((name, "(tmp var of 8 bytes)")) => {
+                    match name
+                        .strip_prefix("tmp_")
+                        .and_then(|i| i.parse::<usize>().ok())
+                    {
+                        Some(i) => temps.push(i),
+                        None => return err!("FATAL {pos}: Invalid temporary name"),
+                    }
+                }
+                _ => return err!("FATAL {pos}: Invalid variable"),
+            }
+        }
+
+        if let (Some(first), Some(last)) = (temps.first(), temps.last()) {
+            function.temps = *first..last + 1;
+            if !temps.iter().copied().eq(function.temps.clone()) {
+                return err!("FATAL in {name}: Temporaries must be numbered consecutively");
+            }
+        }
+
+        while let Some(line) = lines.next_if(|line| !line.text.starts_with("[BEGIN ")) {
+            // Positions are into the program the IR came from, which isn't around
+            if line.text.starts_with("loc ") {
+                continue;
+            }
+
+            match parse_quad(line.text, &program) {
+                Some(quad) => {
+                    if let Some(label) = quad.label() {
+                        intermediate_code::claim_lbl(label);
+                    }
+                    function.body.push(quad);
+                }
+                None => {
+                    let pos = line.source_position();
+                    return err!("FATAL {pos}: Invalid quad");
+                }
+            }
+        }
+
+        program.functions.push(function);
+    }
+
+    verify(&program)?;
+    Ok(program)
+}
+
+/// What's between a prefix and a suffix
+fn enclosed<'a>(text: &'a str, prefix: &str, suffix: &str) -> Option<&'a str> {
+    text.strip_prefix(prefix)?.strip_suffix(suffix)
+}
+
+/// glb_<name> (<type> global of 8 bytes)
+fn parse_global(text: &str) -> Option<Global> {
+    let (name, t) = text.strip_prefix("glb_")?.split_once(' ')?;
+    let t = enclosed(t, "(", " global of 8 bytes)")?;
+
+    let pos = SourcePositionData { s: 0, e: 0 };
+    let (t, perfect) = match t.strip_prefix("perfect ") {
+        Some(t) => (t, true),
+        None => (t, false),
+    };
+
+    let primitive = match t {
+        "bool" => Some(Primitive::Bool),
+        "int" => Some(Primitive::Int),
+        "string" => Some(Primitive::String),
+        "void" => Some(Primitive::Void),
+        _ => None,
+    };
+
+    let t = match (primitive, perfect) {
+        (Some(primitive), false) => Type::Primitive(primitive, pos),
+        (Some(primitive), true) => Type::PerfectPrimitive(primitive, pos),
+        (None, perfect) => {
+            let id = Id {
+                name: t.to_string(),
+                source_position: pos,
+            };
+
+            match perfect {
+                false => Type::Class(id, pos),
+                true => Type::PerfectClass(id, pos),
+            }
+        }
+    };
+
+    Some(Global {
+        name: name.to_string(),
+        t,
+    })
+}
+
+/// <label> "<value>", with the value kept as written
+fn parse_string(text: &str) -> Option<StringConstant> {
+    let (label, value) = text.split_once(' ')?;
+    let value = enclosed(value, "\"", "\"")?;
+
+    Some(StringConstant {
+        label: label.to_string(),
+        value: value.to_string(),
+    })
+}
+
+/// 5, [x] or x for a literal, local or global
+fn parse_argument(text: &str) -> Option<Argument> {
+    if let Some(name) = enclosed(text, "[", "]") {
+        return Some(Argument::Local(name.to_string()));
+    }
+
+    if text.starts_with(|c: char| c.is_ascii_digit()) {
+        return text.parse().ok().map(Argument::Literal);
+    }
+
+    match text.is_empty() || text.contains(['[', ']']) {
+        true => None,
+        false => Some(Argument::Global(text.to_string())),
+    }
+}
+
+/// An argument written to, which gets an extra pair of brackets
+fn parse_destination(text: &str) -> Option<Argument> {
+    parse_argument(enclosed(text, "[", "]")?)
+}
+
+fn parse_quad(text: &str, program: &IrProgram) -> Option<Quad> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+
+    let quad = match words.as_slice() {
+        [label, "enter", name] if label.strip_suffix(':')? == format!("fn_{name}") => {
+            Quad::Enter(name.to_string())
+        }
+        [label, "leave", name] => {
+            Quad::Leave(label.strip_suffix(':')?.to_string(), name.to_string())
+        }
+        [label, "nop"] => Quad::Label(label.strip_suffix(':')?.to_string()),
+        ["call", name] => Quad::Call(name.strip_prefix("fn_")?.to_string()),
+        ["callsite", site] => Quad::CallSite(site.to_string()),
+        ["tailcall", name] => Quad::TailCall(name.strip_prefix("fn_")?.to_string()),
+        ["exit"] => Quad::Exit,
+        ["check", x, operation, y, message] => {
+            let check = match *operation {
+                "ADD64" => Check::Add,
+                "DIV64" => Check::Divide,
+                "MULT64" => Check::Multiply,
+                "SUB64" => Check::Subtract,
+                _ => return None,
+            };
+
+            Quad::Check(
+                check,
+                parse_argument(x)?,
+                parse_argument(y)?,
+                message.to_string(),
+            )
+        }
+        ["getarg", n, x] => Quad::GetArg(n.parse().ok()?, parse_destination(x)?),
+        ["getret", w] => Quad::GetRet(parse_destination(w)?),
+        ["goto", label] => Quad::Goto(label.to_string()),
+        ["ifz", c, "goto", label] => Quad::Ifz(parse_argument(c)?, label.to_string()),
+        ["read", w, message] => Quad::ReadInt(parse_argument(w)?, message.to_string()),
+        ["readbool", w, message] => Quad::ReadBool(parse_argument(w)?, message.to_string()),
+        ["setarg", n, x] => Quad::SetArg(n.parse().ok()?, parse_argument(x)?),
+        ["setret", x] => Quad::SetRet(parse_argument(x)?),
+        ["writebool", x] => Quad::WriteBool(parse_argument(x)?),
+        ["write", x] => match parse_argument(x)? {
+            Argument::Global(label) if program.strings.iter().any(|s| s.label == label) => {
+                Quad::WriteStr(Argument::Global(label))
+            }
+            x => Quad::WriteInt(x),
+        },
+        [w, ":=", "NOT64", x] => Quad::Not(parse_destination(w)?, parse_argument(x)?),
+        [w, ":=", x] => Quad::Assignment(parse_destination(w)?, parse_argument(x)?),
+        [w, ":=", x, operation, y] => {
+            let (w, x, y) = (
+                parse_destination(w)?,
+                parse_argument(x)?,
+                parse_argument(y)?,
+            );
+
+            match *operation {
+                "ADD64" => Quad::Add(w, x, y),
+                "AND64" => Quad::And(w, x, y),
+                "DIV64" => Quad::Divide(w, x, y),
+                "EQ64" => Quad::Equals(w, x, y),
+                "GT64" => Quad::Greater(w, x, y),
+                "GTE64" => Quad::GreaterEq(w, x, y),
+                "LT64" => Quad::Less(w, x, y),
+                "LTE64" => Quad::LessEq(w, x, y),
+                "MULT64" => Quad::Multiply(w, x, y),
+                "NEQ64" => Quad::NotEq(w, x, y),
+                "OR64" => Quad::Or(w, x, y),
+                "SHL64" => Quad::ShiftLeft(w, x, y),
+                "SHR64" => Quad::ShiftRight(w, x, y),
+                "SUB64" => Quad::Subtract(w, x, y),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    Some(quad)
+}
@@ -0,0 +1,282 @@
+//! # Constant Folding
+//! Evaluates operations on literals at compile time and propagates the
+//! results through locals, and through globals that are only ever written
+//! once with a literal. Branches on constants become plain jumps.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::{
+    err,
+    three_ac::{self, Argument, Cfg, Check, IrFunction, IrProgram, Quad, SsaFunction, INIT_FN},
+};
+
+pub fn run(program: &mut IrProgram) -> Result<()> {
+    // Initializers run before anything else, so once they're folded, constant
+    // globals can be substituted everywhere but the initializer itself
+    let names: Vec<String> = program.globals.iter().map(|g| g.name.clone()).collect();
+    for function in &mut program.functions {
+        if function.name == INIT_FN {
+            fold_function(function);
+            while propagate_through_init(function, &names) {
+                fold_function(function);
+            }
+        }
+    }
+
+    let globals = get_constant_globals(program);
+    for function in &mut program.functions {
+        if function.name != INIT_FN {
+            substitute_globals(function, &globals);
+            fold_function(function);
+        }
+    }
+
+    // Folding can leave a division by a literal zero that wasn't written as one
+    check_division(program)
+}
+
+fn fold_function(function: &mut IrFunction) {
+    let mut ssa = SsaFunction::new(function);
+    propagate(&mut ssa);
+    *function = ssa.to_function();
+    simplify_branches(function);
+}
+
+/// Folds quads and phis until no more constants can be found
+fn propagate(ssa: &mut SsaFunction) {
+    let mut constants: HashMap<String, u64> = HashMap::new();
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for (block, phis) in ssa.cfg.blocks.iter_mut().zip(&mut ssa.phis) {
+            for phi in phis.iter_mut() {
+                for arg in &mut phi.args {
+                    substitute(arg, &constants);
+                }
+
+                if let Some(value) = get_phi_constant(&phi.dest, &phi.args) {
+                    changed |= constants.insert(phi.dest.clone(), value).is_none();
+                }
+            }
+
+            for quad in &mut block.quads {
+                for arg in quad.uses_mut() {
+                    substitute(arg, &constants);
+                }
+
+                if let Some(folded) = fold(quad) {
+                    *quad = folded;
+                }
+
+                if let Quad::Assignment(Argument::Local(name), Argument::Literal(value)) = quad {
+                    changed |= constants.insert(name.clone(), *value).is_none();
+                }
+            }
+        }
+    }
+}
+
+fn substitute(arg: &mut Argument, constants: &HashMap<String, u64>) {
+    if let Some(value) = arg.local().and_then(|name| constants.get(name)) {
+        *arg = Argument::Literal(*value);
+    }
+}
+
+/// A phi is constant if every incoming value is the same literal, ignoring
+/// the phi feeding itself around a loop
+fn get_phi_constant(dest: &String, args: &[Argument]) -> Option<u64> {
+    let mut constant = None;
+
+    for arg in args {
+        match arg {
+            Argument::Literal(value) if constant.is_none() || constant == Some(*value) => {
+                constant = Some(*value)
+            }
+            Argument::Local(name) if name == dest => (),
+            _ => return None,
+        }
+    }
+
+    constant
+}
+
+/// Evaluates a quad whose operands are all literals
+fn fold(quad: &Quad) -> Option<Quad> {
+    let value = match quad {
+        Quad::Not(_, Argument::Literal(x)) => x ^ 1,
+        Quad::Add(_, Argument::Literal(x), Argument::Literal(y)) => x.wrapping_add(*y),
+        Quad::And(_, Argument::Literal(x), Argument::Literal(y)) => x & y,
+        Quad::Divide(_, Argument::Literal(x), Argument::Literal(y)) => {
+            // Leave anything that would trap for runtime
+            let (x, y) = (*x as i64, *y as i64);
+            x.checked_div(y)? as u64
+        }
+        Quad::Equals(_, Argument::Literal(x), Argument::Literal(y)) => (x == y) as u64,
+        Quad::Greater(_, Argument::Literal(x), Argument::Literal(y)) => {
+            (*x as i64 > *y as i64) as u64
+        }
+        Quad::GreaterEq(_, Argument::Literal(x), Argument::Literal(y)) => {
+            (*x as i64 >= *y as i64) as u64
+        }
+        Quad::Less(_, Argument::Literal(x), Argument::Literal(y)) => {
+            ((*x as i64) < *y as i64) as u64
+        }
+        Quad::LessEq(_, Argument::Literal(x), Argument::Literal(y)) => {
+            (*x as i64 <= *y as i64) as u64
+        }
+        Quad::Multiply(_, Argument::Literal(x), Argument::Literal(y)) => x.wrapping_mul(*y),
+        Quad::NotEq(_, Argument::Literal(x), Argument::Literal(y)) => (x != y) as u64,
+        Quad::Or(_, Argument::Literal(x), Argument::Literal(y)) => x | y,
+        Quad::ShiftLeft(_, Argument::Literal(x), Argument::Literal(y)) => {
+            x.checked_shl(u32::try_from(*y).ok()?)?
+        }
+        Quad::ShiftRight(_, Argument::Literal(x), Argument::Literal(y)) => {
+            (*x as i64).checked_shr(u32::try_from(*y).ok()?)? as u64
+        }
+        Quad::Subtract(_, Argument::Literal(x), Argument::Literal(y)) => x.wrapping_sub(*y),
+        _ => return None,
+    };
+
+    let location = quad.def()?.clone();
+    Some(Quad::Assignment(location, Argument::Literal(value)))
+}
+
+/// Turns branches on constant conditions into jumps, or removes them
+fn simplify_branches(function: &mut IrFunction) {
+    let mut body = Vec::new();
+
+    for quad in function.body.drain(..) {
+        match quad {
+            Quad::Ifz(Argument::Literal(0), label) => body.push(Quad::Goto(label)),
+            Quad::Ifz(Argument::Literal(_), _) => (),
+            quad => body.push(quad),
+        }
+    }
+
+    function.body = body;
+}
+
+/// Initializers run in order, so globals can be followed through them. Globals
+/// start out zeroed, and anything could change once control flow gets involved.
+fn propagate_through_init(function: &mut IrFunction, globals: &[String]) -> bool {
+    let mut values: HashMap<String, u64> = globals.iter().map(|g| (g.clone(), 0)).collect();
+    let mut changed = false;
+
+    for quad in &mut function.body {
+        if quad.label().is_some() || quad.ends_block() || quad.callee().is_some() {
+            values.clear();
+        }
+
+        for arg in quad.uses_mut() {
+            if let Some(value) = get_global(arg).and_then(|name| values.get(name)) {
+                *arg = Argument::Literal(*value);
+                changed = true;
+            }
+        }
+
+        match quad {
+            Quad::Assignment(Argument::Global(name), Argument::Literal(value)) => {
+                values.insert(name.clone(), *value);
+            }
+            quad => {
+                if let Some(name) = quad.def().and_then(get_global) {
+                    values.remove(name);
+                }
+            }
+        }
+    }
+
+    changed
+}
+
+fn get_global(arg: &Argument) -> Option<&String> {
+    match arg {
+        Argument::Global(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// Globals written exactly once, with a literal, by the initializer before it
+/// can call anything. Globals that are never written are always zero.
+fn get_constant_globals(program: &IrProgram) -> HashMap<String, u64> {
+    let mut writes: HashMap<&String, usize> = HashMap::new();
+    let mut values = HashMap::new();
+
+    for function in &program.functions {
+        let mut calls_made = false;
+
+        for quad in &function.body {
+            if quad.callee().is_some() {
+                calls_made = true;
+            }
+
+            let Some(Argument::Global(name)) = quad.def() else {
+                continue;
+            };
+            *writes.entry(name).or_insert(0) += 1;
+
+            match quad {
+                Quad::Assignment(_, Argument::Literal(value))
+                    if function.name == INIT_FN && !calls_made =>
+                {
+                    values.insert(name.clone(), *value);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    program
+        .globals
+        .iter()
+        .filter_map(|global| match writes.get(&global.name) {
+            None => Some((global.name.clone(), 0)),
+            Some(1) => values.get(&global.name).map(|v| (global.name.clone(), *v)),
+            Some(_) => None,
+        })
+        .collect()
+}
+
+fn substitute_globals(function: &mut IrFunction, globals: &HashMap<String, u64>) {
+    for quad in &mut function.body {
+        for arg in quad.uses_mut() {
+            if let Some(value) = get_global(arg).and_then(|name| globals.get(name)) {
+                *arg = Argument::Literal(*value);
+            }
+        }
+    }
+}
+
+/// Division by a literal zero the program can reach is an error, unless a
+/// runtime check stops the program before it
+pub fn check_division(program: &IrProgram) -> Result<()> {
+    for function in &program.functions {
+        let mut function = function.clone();
+        simplify_branches(&mut function);
+        let cfg = Cfg::new(&function);
+
+        for block in cfg.reverse_postorder() {
+            let quads = &cfg.blocks[block].quads;
+
+            for (i, quad) in quads.iter().enumerate() {
+                let checked = i > 0 && matches!(quads[i - 1], Quad::Check(Check::Divide, ..));
+
+                let (Quad::Divide(location, _, Argument::Literal(0)), false) = (quad, checked)
+                else {
+                    continue;
+                };
+
+                return match three_ac::get_division(&function.name, location) {
+                    Some(pos) => err!("FATAL {pos}: Division by zero"),
+                    None => err!("FATAL in {}: Division by zero", function.name),
+                };
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! # Dead Code Elimination
+//! Removes code that can never run, writes to locals that are never read,
+//! and labels nothing jumps to, then drops the variables that are left unused.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::three_ac::{Argument, Cfg, IrFunction, IrProgram, Liveness, Quad};
+
+pub fn run(program: &mut IrProgram) {
+    for function in &mut program.functions {
+        remove_unreachable_blocks(function);
+
+        while remove_dead_quads(function) {}
+        while remove_useless_jumps(function) {}
+
+        remove_unused_variables(function);
+    }
+}
+
+/// Drops blocks that can't be reached from the entry. The exit block stays so
+/// the function keeps its epilogue.
+fn remove_unreachable_blocks(function: &mut IrFunction) {
+    let cfg = Cfg::new(function);
+    let reachable: HashSet<usize> = cfg.reverse_postorder().into_iter().collect();
+
+    function.body = cfg
+        .blocks
+        .into_iter()
+        .enumerate()
+        .filter(|(i, block)| {
+            reachable.contains(i) || matches!(block.quads.first(), Some(Quad::Leave(_, _)))
+        })
+        .flat_map(|(_, block)| block.quads)
+        .collect();
+}
+
+/// Removes quads whose only effect is writing a local that's never read.
+/// Returns whether anything was removed.
+fn remove_dead_quads(function: &mut IrFunction) -> bool {
+    let cfg = Cfg::new(function);
+    let liveness = Liveness::new(&cfg);
+    let mut removed = false;
+    let mut body = Vec::new();
+
+    for (i, block) in cfg.blocks.iter().enumerate() {
+        let live_after = liveness.live_after_quads(&cfg, i);
+
+        for (quad, live) in block.quads.iter().zip(live_after) {
+            let dead = match quad.def() {
+                Some(Argument::Local(name)) => !live.contains(name) && is_pure(quad),
+                _ => false,
+            };
+
+            if dead {
+                removed = true;
+            } else {
+                body.push(quad.clone());
+            }
+        }
+    }
+
+    function.body = body;
+    removed
+}
+
+/// Whether a quad does nothing besides writing its result. A division could
+/// trap instead, unless it's by a literal other than 0, or -1 which traps on
+/// the smallest int.
+fn is_pure(quad: &Quad) -> bool {
+    match quad {
+        Quad::Divide(_, _, Argument::Literal(y)) => *y != 0 && *y as i64 != -1,
+        Quad::Divide(_, _, _) | Quad::ReadBool(_, _) | Quad::ReadInt(_, _) => false,
+        _ => true,
+    }
+}
+
+/// Removes jumps to the very next quad and labels nothing jumps to. Returns
+/// whether anything was removed.
+fn remove_useless_jumps(function: &mut IrFunction) -> bool {
+    let targets: HashSet<String> = function
+        .body
+        .iter()
+        .filter_map(|quad| quad.jump_target().cloned())
+        .collect();
+
+    let length = function.body.len();
+    let mut body = Vec::new();
+
+    for (i, quad) in function.body.iter().enumerate() {
+        match quad {
+            Quad::Label(label) if !targets.contains(label) => continue,
+            Quad::Goto(label) if jumps_to_next(&function.body[i + 1..], label) => continue,
+            _ => body.push(quad.clone()),
+        }
+    }
+
+    function.body = body;
+    function.body.len() != length
+}
+
+/// Whether a label comes up before any real code does. Falling into a leave
+/// isn't the same as jumping to it, since that returns zero.
+fn jumps_to_next(rest: &[Quad], label: &String) -> bool {
+    for quad in rest {
+        match quad {
+            Quad::Label(l) if l == label => return true,
+            Quad::Label(_) | Quad::Loc(_) => continue,
+            _ => return false,
+        }
+    }
+
+    false
+}
+
+/// Forgets locals that no longer appear in the body, and renumbers the
+/// remaining temps so the function's temp range is as small as it can be
+fn remove_unused_variables(function: &mut IrFunction) {
+    let mut used = HashSet::new();
+    for quad in &function.body {
+        let arguments = quad.uses().into_iter().chain(quad.def());
+        used.extend(arguments.filter_map(|a| a.local()).cloned());
+    }
+
+    function.locals.retain(|local| used.contains(local));
+
+    let start = function.temps.start;
+    let mut renames = HashMap::new();
+    for i in function.temps.clone() {
+        let name = format!("tmp_{i}");
+        if used.contains(&name) {
+            renames.insert(name, format!("tmp_{}", start + renames.len()));
+        }
+    }
+    function.temps = start..start + renames.len();
+
+    let rename = |argument: &mut Argument| {
+        if let Some(name) = argument.local().and_then(|name| renames.get(name)) {
+            *argument = Argument::Local(name.clone());
+        }
+    };
+
+    for quad in &mut function.body {
+        quad.uses_mut().into_iter().for_each(rename);
+        quad.def_mut().map(rename);
+    }
+}
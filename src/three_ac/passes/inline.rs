@@ -0,0 +1,268 @@
+//! # Function Inlining
+//! Replaces calls to small functions with a copy of their body. The copy gets
+//! its own variables and labels, arguments become assignments to its formals,
+//! and returns become assignments to the call's result. Functions that can
+//! reach themselves through calls are never inlined. Nothing is inlined when
+//! backtraces are on, since they list every call.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::three_ac::{intermediate_code, Argument, IrFunction, IrProgram, Quad};
+
+/// Largest callee body worth copying into its callers
+const MAX_INLINE_SIZE: usize = 24;
+
+pub fn run(program: &mut IrProgram) {
+    if intermediate_code::get_backtraces() {
+        return;
+    }
+
+    let calls = get_call_graph(program);
+
+    // Callees go first, so whatever they inlined themselves comes along
+    for name in get_postorder(&calls) {
+        let Some(index) = program.functions.iter().position(|f| f.name == name) else {
+            continue;
+        };
+
+        let mut function = program.functions[index].clone();
+        inline_calls(&mut function, program, &calls);
+        program.functions[index] = function;
+    }
+}
+
+/// Functions each function calls directly
+fn get_call_graph(program: &IrProgram) -> HashMap<String, Vec<String>> {
+    let mut calls = HashMap::new();
+
+    for function in &program.functions {
+        let mut callees: Vec<String> = Vec::new();
+        for quad in &function.body {
+            if let Some(callee) = quad.callee() {
+                if !callees.contains(callee) {
+                    callees.push(callee.clone());
+                }
+            }
+        }
+
+        calls.insert(function.name.clone(), callees);
+    }
+
+    calls
+}
+
+/// Every function, with callees before their callers where there's no recursion
+fn get_postorder(calls: &HashMap<String, Vec<String>>) -> Vec<String> {
+    fn visit(
+        name: &String,
+        calls: &HashMap<String, Vec<String>>,
+        visited: &mut HashSet<String>,
+        order: &mut Vec<String>,
+    ) {
+        if !visited.insert(name.clone()) {
+            return;
+        }
+
+        for callee in calls.get(name).into_iter().flatten() {
+            visit(callee, calls, visited, order);
+        }
+        order.push(name.clone());
+    }
+
+    let mut names: Vec<&String> = calls.keys().collect();
+    names.sort();
+
+    let mut visited = HashSet::new();
+    let mut order = Vec::new();
+    for name in names {
+        visit(name, calls, &mut visited, &mut order);
+    }
+
+    order
+}
+
+/// Whether a function can end up calling itself
+fn is_recursive(name: &String, calls: &HashMap<String, Vec<String>>) -> bool {
+    let mut stack: Vec<&String> = calls.get(name).into_iter().flatten().collect();
+    let mut visited = HashSet::new();
+
+    while let Some(callee) = stack.pop() {
+        if callee == name {
+            return true;
+        }
+
+        if visited.insert(callee) {
+            stack.extend(calls.get(callee).into_iter().flatten());
+        }
+    }
+
+    false
+}
+
+/// Quads in a function, not counting debug information
+fn size(function: &IrFunction) -> usize {
+    let quads = function.body.iter();
+    quads.filter(|quad| !matches!(quad, Quad::Loc(_))).count()
+}
+
+/// Tail calls return from the function they're in, so they can't be copied
+/// into another one
+fn has_tail_calls(function: &IrFunction) -> bool {
+    let mut quads = function.body.iter();
+    quads.any(|quad| matches!(quad, Quad::TailCall(_)))
+}
+
+fn inline_calls(
+    function: &mut IrFunction,
+    program: &IrProgram,
+    calls: &HashMap<String, Vec<String>>,
+) {
+    let mut body = Vec::new();
+    let mut args = Vec::new();
+    let mut sites = 0;
+    let original = std::mem::take(&mut function.body);
+    let mut quads = original.iter().peekable();
+
+    while let Some(quad) = quads.next() {
+        match quad {
+            Quad::SetArg(_, _) => {
+                args.push(quad.clone());
+                continue;
+            }
+            Quad::Call(name) => {
+                let callee = program
+                    .get_function(name)
+                    .filter(|callee| callee.name != function.name)
+                    .filter(|callee| size(callee) <= MAX_INLINE_SIZE)
+                    .filter(|callee| !is_recursive(&callee.name, calls))
+                    .filter(|callee| !has_tail_calls(callee));
+
+                if let Some(callee) = callee {
+                    let result = quads.next_if(|q| matches!(q, Quad::GetRet(_)));
+                    let result = result.and_then(|q| q.def());
+
+                    sites += 1;
+                    let inlined = Inlined::new(function, callee, sites);
+                    body.extend(inlined.copy_body(callee, &args, result));
+                    args.clear();
+                    continue;
+                }
+            }
+            _ => (),
+        }
+
+        body.append(&mut args);
+        body.push(quad.clone());
+    }
+
+    body.append(&mut args);
+    function.body = body;
+}
+
+/// Names given to one copy of a callee
+struct Inlined {
+    variables: HashMap<String, String>,
+    labels: HashMap<String, String>,
+}
+
+impl Inlined {
+    /// Declares fresh variables in the caller for everything the callee uses
+    fn new(caller: &mut IrFunction, callee: &IrFunction, site: usize) -> Self {
+        let mut variables = HashMap::new();
+
+        for name in callee.formals.iter().chain(&callee.locals) {
+            let renamed = format!("{name}@{}.{site}", callee.name);
+            caller.locals.push(renamed.clone());
+            variables.insert(name.clone(), renamed);
+        }
+
+        for i in callee.temps.clone() {
+            let temp = Argument::Local(format!("tmp_{i}"));
+            let renamed = caller.new_tmp();
+            if let Some(pos) = intermediate_code::get_division(&callee.name, &temp) {
+                let location = Argument::Local(renamed.clone());
+                intermediate_code::add_division(Some(&caller.name), &location, pos);
+            }
+            variables.insert(format!("tmp_{i}"), renamed);
+        }
+
+        let mut labels = HashMap::new();
+        for quad in &callee.body {
+            if let Some(label) = quad.label() {
+                labels.insert(label.clone(), intermediate_code::get_lbl());
+            }
+        }
+
+        Self { variables, labels }
+    }
+
+    fn copy_body(
+        &self,
+        callee: &IrFunction,
+        args: &[Quad],
+        result: Option<&Argument>,
+    ) -> Vec<Quad> {
+        let after = intermediate_code::get_lbl();
+        let mut body = Vec::new();
+
+        for quad in &callee.body {
+            let quad = match quad {
+                Quad::Enter(_) => continue,
+                Quad::GetArg(number, formal) => {
+                    let value = args.iter().find_map(|arg| match arg {
+                        Quad::SetArg(n, value) if n == number => Some(value.clone()),
+                        _ => None,
+                    });
+
+                    Quad::Assignment(self.rename(formal), value.unwrap_or(Argument::Literal(0)))
+                }
+                Quad::SetRet(value) => match result {
+                    Some(result) => Quad::Assignment(result.clone(), self.rename(value)),
+                    None => continue,
+                },
+                Quad::Leave(label, _) => {
+                    // Falling into the exit returns zero, while jumping to it
+                    // returns whatever was set before the jump
+                    if let Some(result) = result {
+                        body.push(Quad::Assignment(result.clone(), Argument::Literal(0)));
+                    }
+                    body.push(Quad::Label(self.labels[label].clone()));
+                    Quad::Goto(after.clone())
+                }
+                _ => {
+                    let mut quad = quad.clone();
+                    self.rename_quad(&mut quad);
+                    quad
+                }
+            };
+
+            body.push(quad);
+        }
+
+        body.push(Quad::Label(after));
+        body
+    }
+
+    fn rename(&self, argument: &Argument) -> Argument {
+        match argument.local().and_then(|name| self.variables.get(name)) {
+            Some(name) => Argument::Local(name.clone()),
+            None => argument.clone(),
+        }
+    }
+
+    fn rename_quad(&self, quad: &mut Quad) {
+        for argument in quad.uses_mut() {
+            *argument = self.rename(argument);
+        }
+        if let Some(argument) = quad.def_mut() {
+            *argument = self.rename(argument);
+        }
+
+        match quad {
+            Quad::Label(label) | Quad::Goto(label) | Quad::Ifz(_, label) => {
+                *label = self.labels[label].clone();
+            }
+            _ => (),
+        }
+    }
+}
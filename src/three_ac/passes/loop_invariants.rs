@@ -0,0 +1,183 @@
+//! # Loop Invariant Code Motion
+//! Hoists quads whose result is the same on every iteration of a loop into a
+//! preheader block, which runs once before the loop is entered.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::three_ac::{
+    dominates, find_loops, intermediate_code, Argument, Cfg, IrFunction, IrProgram, Liveness, Loop,
+    Quad,
+};
+
+pub fn run(program: &mut IrProgram) {
+    for function in &mut program.functions {
+        // Hoisting changes the graph, so start over after every loop that changes
+        while hoist_from_a_loop(function) {}
+    }
+}
+
+fn hoist_from_a_loop(function: &mut IrFunction) -> bool {
+    let cfg = Cfg::new(function);
+    let idom = cfg.immediate_dominators();
+    let liveness = Liveness::new(&cfg);
+
+    for l in find_loops(&cfg, &idom) {
+        if !can_add_preheader(&cfg, &l) {
+            continue;
+        }
+
+        let invariants = find_invariants(&cfg, &idom, &liveness, &l);
+        if !invariants.is_empty() {
+            add_preheader(function, &cfg, &l, &invariants);
+            return true;
+        }
+    }
+
+    false
+}
+
+/// The preheader goes right before the header, so that only works if nothing
+/// in the loop falls through into the header
+fn can_add_preheader(cfg: &Cfg, l: &Loop) -> bool {
+    let header = &cfg.blocks[l.header];
+    if header.label().is_none() {
+        return false;
+    }
+
+    match l.header.checked_sub(1) {
+        Some(previous) => !(l.contains(previous) && header.predecessors.contains(&previous)),
+        None => false,
+    }
+}
+
+/// Quads in a loop, as (block, index) pairs in the order they'll be hoisted.
+/// A quad is invariant when its operands are, and it's safe to hoist when
+/// it's the only write to its location in the loop and running it early
+/// can't be seen from anywhere the old value could be read.
+fn find_invariants(
+    cfg: &Cfg,
+    idom: &[Option<usize>],
+    liveness: &Liveness,
+    l: &Loop,
+) -> Vec<(usize, usize)> {
+    let mut definitions: HashMap<&Argument, usize> = HashMap::new();
+    let mut has_calls = false;
+
+    for block in &l.blocks {
+        for quad in &cfg.blocks[*block].quads {
+            if let Some(def) = quad.def() {
+                *definitions.entry(def).or_insert(0) += 1;
+            }
+            has_calls |= quad.callee().is_some();
+        }
+    }
+
+    let exits = l.exits(cfg);
+    let exiting: Vec<usize> = l
+        .blocks
+        .iter()
+        .copied()
+        .filter(|b| cfg.blocks[*b].successors.iter().any(|s| !l.contains(*s)))
+        .collect();
+
+    let mut hoisted: Vec<(usize, usize)> = Vec::new();
+    let mut invariant: HashSet<&Argument> = HashSet::new();
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for block in &l.blocks {
+            for (i, quad) in cfg.blocks[*block].quads.iter().enumerate() {
+                if hoisted.contains(&(*block, i)) || !is_movable(quad) {
+                    continue;
+                }
+
+                let Some(def @ Argument::Local(name)) = quad.def() else {
+                    continue;
+                };
+
+                let operands_invariant = quad.uses().into_iter().all(|arg| match arg {
+                    Argument::Literal(_) => true,
+                    Argument::Local(_) => !definitions.contains_key(arg) || invariant.contains(arg),
+                    Argument::Global(_) => !definitions.contains_key(arg) && !has_calls,
+                });
+
+                let only_definition = definitions[def] == 1;
+                let fresh = !liveness.live_in[l.header].contains(name);
+                let unseen = exiting.iter().all(|e| dominates(idom, *block, *e))
+                    || exits.iter().all(|e| !liveness.live_in[*e].contains(name));
+
+                if operands_invariant && only_definition && fresh && unseen {
+                    hoisted.push((*block, i));
+                    invariant.insert(def);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    hoisted
+}
+
+/// Whether a quad computes a value and does nothing else. Division is left
+/// out, since hoisting it could trap in a loop that never runs.
+fn is_movable(quad: &Quad) -> bool {
+    matches!(
+        quad,
+        Quad::Add(_, _, _)
+            | Quad::And(_, _, _)
+            | Quad::Assignment(_, _)
+            | Quad::Equals(_, _, _)
+            | Quad::Greater(_, _, _)
+            | Quad::GreaterEq(_, _, _)
+            | Quad::Less(_, _, _)
+            | Quad::LessEq(_, _, _)
+            | Quad::Multiply(_, _, _)
+            | Quad::Not(_, _)
+            | Quad::NotEq(_, _, _)
+            | Quad::Or(_, _, _)
+            | Quad::ShiftLeft(_, _, _)
+            | Quad::ShiftRight(_, _, _)
+            | Quad::Subtract(_, _, _)
+    )
+}
+
+/// Moves the invariant quads into a new block before the header, and sends
+/// every jump into the loop from outside through it
+fn add_preheader(function: &mut IrFunction, cfg: &Cfg, l: &Loop, invariants: &[(usize, usize)]) {
+    let header = cfg.blocks[l.header].label().unwrap().clone();
+    let preheader = intermediate_code::get_lbl();
+
+    let mut blocks: Vec<Vec<Quad>> = cfg.blocks.iter().map(|b| b.quads.clone()).collect();
+
+    for (i, block) in blocks.iter_mut().enumerate() {
+        if l.contains(i) {
+            continue;
+        }
+
+        if let Some(Quad::Goto(label) | Quad::Ifz(_, label)) = block.last_mut() {
+            if *label == header {
+                *label = preheader.clone();
+            }
+        }
+    }
+
+    let mut hoisted = vec![Quad::Label(preheader)];
+    hoisted.extend(invariants.iter().map(|(b, i)| blocks[*b][*i].clone()));
+
+    let mut body = Vec::new();
+    for (b, block) in blocks.into_iter().enumerate() {
+        if b == l.header {
+            body.append(&mut hoisted);
+        }
+
+        for (i, quad) in block.into_iter().enumerate() {
+            if !invariants.contains(&(b, i)) {
+                body.push(quad);
+            }
+        }
+    }
+
+    function.body = body;
+}
@@ -0,0 +1,85 @@
+//! # Optimization Passes
+//! Transformations that rewrite a whole IR program into a faster equivalent,
+//! run in a pipeline chosen by optimization level or named one by one
+
+mod constant_folding;
+mod dead_code;
+mod inline;
+mod loop_invariants;
+mod strength_reduction;
+mod tail_calls;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+
+use super::{verify, IrProgram};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Pass {
+    /// Copy small non-recursive functions into their callers
+    Inline,
+    /// Evaluate operations on constants and propagate them
+    ConstantFolding,
+    /// Move loop invariant quads out of their loops
+    LoopInvariants,
+    /// Turn multiplication and division by powers of two into shifts
+    StrengthReduction,
+    /// Turn calls in tail position into jumps
+    TailCalls,
+    /// Remove unreachable blocks, dead quads and unused variables
+    DeadCode,
+}
+
+/// The passes run at each optimization level
+pub fn get_pipeline(level: u8) -> Vec<Pass> {
+    match level {
+        0 => vec![],
+        1 => vec![Pass::ConstantFolding, Pass::DeadCode],
+        _ => vec![
+            Pass::Inline,
+            Pass::ConstantFolding,
+            Pass::LoopInvariants,
+            Pass::StrengthReduction,
+            Pass::TailCalls,
+            Pass::DeadCode,
+        ],
+    }
+}
+
+/// Rejects division by a literal zero, then runs the passes over the program
+/// in order, dumping the IR to stderr after each run of `print_after`. Debug
+/// builds verify the IR before and after every pass.
+pub fn optimize(program: &mut IrProgram, passes: &[Pass], print_after: Option<Pass>) -> Result<()> {
+    if cfg!(debug_assertions) {
+        verify(program).context("Invalid IR before optimizing")?;
+    }
+    constant_folding::check_division(program)?;
+
+    for pass in passes {
+        run(*pass, program)?;
+
+        let name = pass.to_possible_value().unwrap();
+        if cfg!(debug_assertions) {
+            verify(program).with_context(|| format!("Invalid IR after {}", name.get_name()))?;
+        }
+
+        if print_after == Some(*pass) {
+            eprintln!("[AFTER {}]\n{program}", name.get_name());
+        }
+    }
+
+    Ok(())
+}
+
+fn run(pass: Pass, program: &mut IrProgram) -> Result<()> {
+    match pass {
+        Pass::Inline => inline::run(program),
+        Pass::ConstantFolding => constant_folding::run(program)?,
+        Pass::LoopInvariants => loop_invariants::run(program),
+        Pass::StrengthReduction => strength_reduction::run(program),
+        Pass::TailCalls => tail_calls::run(program),
+        Pass::DeadCode => dead_code::run(program),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+//! # Strength Reduction
+//! Replaces multiplication and division by powers of two with shifts
+
+use crate::three_ac::{Argument, IrFunction, IrProgram, Quad};
+
+pub fn run(program: &mut IrProgram) {
+    for function in &mut program.functions {
+        reduce(function);
+    }
+}
+
+fn reduce(function: &mut IrFunction) {
+    let mut body = Vec::new();
+
+    for quad in std::mem::take(&mut function.body) {
+        match &quad {
+            Quad::Multiply(w, x, y) => match (get_log2(x), get_log2(y)) {
+                (_, Some(shift)) => body.push(multiply(w, x, shift)),
+                (Some(shift), _) => body.push(multiply(w, y, shift)),
+                _ => body.push(quad),
+            },
+            Quad::Divide(w, x, y) => match get_log2(y) {
+                Some(shift) => body.extend(divide(function, w, x, shift)),
+                None => body.push(quad),
+            },
+            _ => body.push(quad),
+        }
+    }
+
+    function.body = body;
+}
+
+/// The power of two a positive literal is
+fn get_log2(argument: &Argument) -> Option<u64> {
+    match argument {
+        Argument::Literal(value) if (*value as i64) > 0 && value.is_power_of_two() => {
+            Some(value.trailing_zeros() as u64)
+        }
+        _ => None,
+    }
+}
+
+fn multiply(w: &Argument, x: &Argument, shift: u64) -> Quad {
+    match shift {
+        0 => Quad::Assignment(w.clone(), x.clone()),
+        _ => Quad::ShiftLeft(w.clone(), x.clone(), Argument::Literal(shift)),
+    }
+}
+
+/// Division rounds towards zero, but shifting rounds down, so negative
+/// numbers get 2^shift - 1 added first
+fn divide(function: &mut IrFunction, w: &Argument, x: &Argument, shift: u64) -> Vec<Quad> {
+    if shift == 0 {
+        return vec![Quad::Assignment(w.clone(), x.clone())];
+    }
+
+    let sign = Argument::Local(function.new_tmp());
+    let bias = Argument::Local(function.new_tmp());
+    let biased = Argument::Local(function.new_tmp());
+
+    vec![
+        Quad::ShiftRight(sign.clone(), x.clone(), Argument::Literal(63)),
+        Quad::And(bias.clone(), sign, Argument::Literal((1 << shift) - 1)),
+        Quad::Add(biased.clone(), x.clone(), bias),
+        Quad::ShiftRight(w.clone(), biased, Argument::Literal(shift)),
+    ]
+}
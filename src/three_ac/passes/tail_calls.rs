@@ -0,0 +1,61 @@
+//! # Tail Call Optimization
+//! Calls whose result is returned right away become tail calls, which jump to
+//! the callee instead of calling it, so it returns straight to our caller and
+//! the stack doesn't grow. This covers recursive calls as well as calls to
+//! other functions. Callees with arguments passed on the stack are left alone,
+//! since their arguments live in the caller's frame. So is everything when
+//! backtraces are on, which need the caller's record to stay.
+
+use std::collections::HashSet;
+
+use crate::{
+    three_ac::{get_backtraces, IrFunction, IrProgram, Quad},
+    x64::ARG_REGISTERS,
+};
+
+pub fn run(program: &mut IrProgram) {
+    if get_backtraces() {
+        return;
+    }
+
+    let stack_args: HashSet<String> = program
+        .functions
+        .iter()
+        .filter(|function| function.formals.len() > ARG_REGISTERS.len())
+        .map(|function| function.name.clone())
+        .collect();
+
+    for function in &mut program.functions {
+        replace_tail_calls(function, &stack_args);
+    }
+}
+
+fn replace_tail_calls(function: &mut IrFunction, stack_args: &HashSet<String>) {
+    let Some(exit) = function.body.iter().find_map(|quad| match quad {
+        Quad::Leave(label, _) => Some(label.clone()),
+        _ => None,
+    }) else {
+        return;
+    };
+
+    let mut body = Vec::new();
+    let mut i = 0;
+
+    while i < function.body.len() {
+        match &function.body[i..] {
+            [Quad::Call(name), Quad::GetRet(result), Quad::SetRet(value), Quad::Goto(label), ..]
+                if result == value && *label == exit && !stack_args.contains(name) =>
+            {
+                body.push(Quad::TailCall(name.clone()));
+                i += 4;
+            }
+            [quad, ..] => {
+                body.push(quad.clone());
+                i += 1;
+            }
+            [] => unreachable!(),
+        }
+    }
+
+    function.body = body;
+}
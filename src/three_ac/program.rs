@@ -0,0 +1,254 @@
+//! # IR Program
+//! Structured form of a whole program's intermediate code, with the data
+//! section kept as data instead of text.
+
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter},
+    ops::Range,
+};
+
+use super::{intermediate_code, Argument, Cfg, Liveness, Quad};
+use crate::{
+    ast::Type,
+    x64::{self, instruction::Instruction, peephole, register_allocation, runtime, X64Target},
+};
+
+/// Name of the synthetic function that runs global initializers before main
+pub const INIT_FN: &str = ".init";
+
+/// A global variable, stored in the data section as glb_<name>
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub name: String,
+    pub t: Type,
+}
+
+/// A string literal, stored in the data section under its label
+#[derive(Debug, Clone)]
+pub struct StringConstant {
+    pub label: String,
+    pub value: String,
+}
+
+/// A function's code along with the variables it needs space for
+#[derive(Debug, Clone)]
+pub struct IrFunction {
+    pub name: String,
+    pub formals: Vec<String>,
+    pub locals: Vec<String>,
+    /// Range of tmp_# variables used in this function
+    pub temps: Range<usize>,
+    pub body: Vec<Quad>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IrProgram {
+    pub globals: Vec<Global>,
+    pub strings: Vec<StringConstant>,
+    /// All functions, including the global initializer
+    pub functions: Vec<IrFunction>,
+}
+
+impl IrFunction {
+    /// Every variable that needs a stack slot, in slot order
+    pub fn variables(&self) -> Vec<String> {
+        let mut variables = self.formals.clone();
+        variables.append(&mut self.locals.clone());
+
+        for i in self.temps.clone() {
+            variables.push(format!("tmp_{i}"));
+        }
+
+        variables
+    }
+
+    /// A new temporary, numbered right after the function's others
+    pub fn new_tmp(&mut self) -> String {
+        self.temps.end += 1;
+        format!("tmp_{}", self.temps.end - 1)
+    }
+}
+
+impl IrProgram {
+    pub fn get_function(&self, name: &str) -> Option<&IrFunction> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+}
+
+impl Display for Global {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "glb_{} ({} global of 8 bytes)", self.name, self.t)
+    }
+}
+
+impl Display for StringConstant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} \"{}\"", self.label, self.value)
+    }
+}
+
+impl Display for IrFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = &self.name;
+        writeln!(f, "[BEGIN {name} LOCALS]")?;
+
+        for formal in &self.formals {
+            writeln!(f, "{formal} (formal arg of 8 bytes)")?;
+        }
+
+        for local in &self.locals {
+            writeln!(f, "{local} (local var of 8 bytes)")?;
+        }
+
+        for i in self.temps.clone() {
+            writeln!(f, "tmp_{i} (tmp var of 8 bytes)")?;
+        }
+
+        writeln!(f, "[END {name} LOCALS]")?;
+
+        for quad in &self.body {
+            write!(f, "{quad}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for IrProgram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "[BEGIN GLOBALS]")?;
+
+        for function in &self.functions {
+            writeln!(f, "fn_{}", function.name)?;
+        }
+
+        for global in &self.globals {
+            writeln!(f, "{global}")?;
+        }
+
+        for string in &self.strings {
+            writeln!(f, "{string}")?;
+        }
+
+        writeln!(f, "[End GLOBALS]")?;
+
+        for function in &self.functions {
+            write!(f, "{function}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl X64Target for IrFunction {
+    fn compile_x64(&self) -> String {
+        x64::reset_fn();
+
+        let registers = register_allocation::allocate(self);
+        for variable in self.variables() {
+            match registers.get(&variable) {
+                Some(register) => x64::define_register(&variable, register),
+                None => x64::define_local(&variable),
+            }
+        }
+
+        if intermediate_code::get_backtraces() && self.name != INIT_FN {
+            x64::define_call_record();
+        }
+
+        for quad in &self.body {
+            if let Quad::SetArg(number, _) = quad {
+                x64::define_outgoing_args(*number);
+            }
+        }
+
+        let cfg = Cfg::new(self);
+        let liveness = Liveness::new(&cfg);
+        let live_after: Vec<HashSet<String>> = (0..cfg.blocks.len())
+            .flat_map(|block| liveness.live_after_quads(&cfg, block))
+            .collect();
+
+        let mut instructions = Vec::new();
+        let mut i = 0;
+
+        // The prologue comes from the function's declaration, like what follows it
+        if let [enter, loc @ Quad::Loc(_), ..] = self.body.as_slice() {
+            instructions.extend(loc.get_x64_instructions());
+            instructions.extend(enter.get_x64_instructions());
+            i = 2;
+        }
+
+        while i < self.body.len() {
+            let quad = &self.body[i];
+
+            // Conditions only used by the branch right after them can jump on the flags
+            if let Some(branch) = self.body.get(i + 1) {
+                let dead = match quad.def() {
+                    Some(Argument::Local(name)) => !live_after[i + 1].contains(name),
+                    _ => false,
+                };
+
+                if let Some(fused) = quad.get_fused_x64_instructions(branch).filter(|_| dead) {
+                    instructions.extend(fused);
+                    i += 2;
+                    continue;
+                }
+            }
+
+            instructions.extend(quad.get_x64_instructions());
+            i += 1;
+        }
+
+        if intermediate_code::get_debug_file().is_some() {
+            instructions.push(Instruction::Cfi("endproc".to_string()));
+        }
+
+        let mut string = String::new();
+        for instruction in peephole::optimize(instructions) {
+            string = format!("{string}{instruction}");
+        }
+
+        string
+    }
+}
+
+impl X64Target for IrProgram {
+    fn compile_x64(&self) -> String {
+        let mut string = ".data\n\
+			true_str: .string \"true\"\n\
+			false_str: .string \"false\"\n"
+            .to_string();
+
+        // Line numbers in the debug information refer to the source file
+        if let Some(path) = intermediate_code::get_debug_file() {
+            string = format!(".file 1 {path:?}\n{string}");
+        }
+
+        for string_constant in &self.strings {
+            let StringConstant { label, value } = string_constant;
+            string = format!("{string}{label}: .string \"{value}\"\n");
+        }
+
+        for global in &self.globals {
+            string = format!("{string}glb_{}: .zero 8\n", global.name);
+        }
+
+        // Backtraces name the functions they go through
+        if intermediate_code::get_backtraces() {
+            for function in self.functions.iter().filter(|f| f.name != INIT_FN) {
+                let name = &function.name;
+                string = format!("{string}name_{name}: .string \"{name}\"\n");
+            }
+        }
+
+        string = format!("{string}{}", runtime::entry());
+
+        for function in &self.functions {
+            string = format!("{string}{}", function.compile_x64());
+        }
+
+        // Nothing runs from the stack, which the linker otherwise has to assume
+        format!("{string}.section .note.GNU-stack,\"\",@progbits\n")
+    }
+}
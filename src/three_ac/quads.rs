@@ -1,10 +1,12 @@
-use std::ops::Range;
-
 use super::Argument;
 use crate::{
-    ast::{Formal, Id},
+    source_position::SourcePositionData,
     three_ac::intermediate_code,
-    x64::{self, X64Target},
+    x64::{
+        self,
+        instruction::{Condition, Instruction, Instruction::*, Operand::*, Operation},
+        runtime,
+    },
 };
 
 #[derive(Debug, Clone)]
@@ -13,13 +15,18 @@ pub enum Quad {
     And(Argument, Argument, Argument),
     Assignment(Argument, Argument),
     Call(String),
+    /// Notes where the function's next call is made from, in the string, for
+    /// backtraces
+    CallSite(String),
+    /// Fails with the start of the error message in the string if the
+    /// operation would divide by zero or overflow
+    Check(Check, Argument, Argument, String),
     Divide(Argument, Argument, Argument),
     Enter(String),
     Exit,
     Equals(Argument, Argument, Argument),
     GetArg(usize, Argument),
     GetRet(Argument),
-    Globals(Vec<String>),
     Goto(String),
     Greater(Argument, Argument, Argument),
     GreaterEq(Argument, Argument, Argument),
@@ -28,21 +35,190 @@ pub enum Quad {
     Leave(String, String),
     Less(Argument, Argument, Argument),
     LessEq(Argument, Argument, Argument),
-    // Locals header with function name, formals, locals, and temp variable range
-    Locals(String, Vec<Formal>, Vec<Id>, Range<usize>),
+    /// Marks the quads after it as coming from a source position, for debug
+    /// information
+    Loc(SourcePositionData),
     Multiply(Argument, Argument, Argument),
     Not(Argument, Argument),
     NotEq(Argument, Argument, Argument),
     Or(Argument, Argument, Argument),
-    Read(Argument),
+    /// Reads a bool, or fails with the start of the error message in the string
+    ReadBool(Argument, String),
+    /// Reads an int, or fails with the start of the error message in the string
+    ReadInt(Argument, String),
     SetArg(usize, Argument),
     SetRet(Argument),
+    ShiftLeft(Argument, Argument, Argument),
+    /// Arithmetic shift, which keeps the sign
+    ShiftRight(Argument, Argument, Argument),
     Subtract(Argument, Argument, Argument),
+    /// Call that replaces the current function, returning straight to its caller
+    TailCall(String),
     WriteBool(Argument),
     WriteInt(Argument),
     WriteStr(Argument),
 }
 
+/// An operation that can fail at runtime
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Check {
+    Add,
+    Divide,
+    Multiply,
+    Subtract,
+}
+
+impl Quad {
+    /// The label this quad defines, if it can be jumped to
+    pub fn label(&self) -> Option<&String> {
+        match self {
+            Quad::Label(label) | Quad::Leave(label, _) => Some(label),
+            _ => None,
+        }
+    }
+
+    /// The label this quad may jump to
+    pub fn jump_target(&self) -> Option<&String> {
+        match self {
+            Quad::Goto(label) | Quad::Ifz(_, label) => Some(label),
+            _ => None,
+        }
+    }
+
+    /// Whether control can continue on to the next quad
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self,
+            Quad::Goto(_) | Quad::Exit | Quad::Leave(_, _) | Quad::TailCall(_)
+        )
+    }
+
+    /// The function this quad calls
+    pub fn callee(&self) -> Option<&String> {
+        match self {
+            Quad::Call(name) | Quad::TailCall(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Whether this quad ends a basic block
+    pub fn ends_block(&self) -> bool {
+        self.jump_target().is_some() || !self.falls_through()
+    }
+
+    /// The argument this quad writes to
+    pub fn def(&self) -> Option<&Argument> {
+        match self {
+            Quad::Add(w, _, _)
+            | Quad::And(w, _, _)
+            | Quad::Assignment(w, _)
+            | Quad::Divide(w, _, _)
+            | Quad::Equals(w, _, _)
+            | Quad::GetArg(_, w)
+            | Quad::GetRet(w)
+            | Quad::Greater(w, _, _)
+            | Quad::GreaterEq(w, _, _)
+            | Quad::Less(w, _, _)
+            | Quad::LessEq(w, _, _)
+            | Quad::Multiply(w, _, _)
+            | Quad::Not(w, _)
+            | Quad::NotEq(w, _, _)
+            | Quad::Or(w, _, _)
+            | Quad::ReadBool(w, _)
+            | Quad::ReadInt(w, _)
+            | Quad::ShiftLeft(w, _, _)
+            | Quad::ShiftRight(w, _, _)
+            | Quad::Subtract(w, _, _) => Some(w),
+            _ => None,
+        }
+    }
+
+    pub fn def_mut(&mut self) -> Option<&mut Argument> {
+        match self {
+            Quad::Add(w, _, _)
+            | Quad::And(w, _, _)
+            | Quad::Assignment(w, _)
+            | Quad::Divide(w, _, _)
+            | Quad::Equals(w, _, _)
+            | Quad::GetArg(_, w)
+            | Quad::GetRet(w)
+            | Quad::Greater(w, _, _)
+            | Quad::GreaterEq(w, _, _)
+            | Quad::Less(w, _, _)
+            | Quad::LessEq(w, _, _)
+            | Quad::Multiply(w, _, _)
+            | Quad::Not(w, _)
+            | Quad::NotEq(w, _, _)
+            | Quad::Or(w, _, _)
+            | Quad::ReadBool(w, _)
+            | Quad::ReadInt(w, _)
+            | Quad::ShiftLeft(w, _, _)
+            | Quad::ShiftRight(w, _, _)
+            | Quad::Subtract(w, _, _) => Some(w),
+            _ => None,
+        }
+    }
+
+    /// The arguments this quad reads from
+    pub fn uses(&self) -> Vec<&Argument> {
+        match self {
+            Quad::Add(_, x, y)
+            | Quad::And(_, x, y)
+            | Quad::Check(_, x, y, _)
+            | Quad::Divide(_, x, y)
+            | Quad::Equals(_, x, y)
+            | Quad::Greater(_, x, y)
+            | Quad::GreaterEq(_, x, y)
+            | Quad::Less(_, x, y)
+            | Quad::LessEq(_, x, y)
+            | Quad::Multiply(_, x, y)
+            | Quad::NotEq(_, x, y)
+            | Quad::Or(_, x, y)
+            | Quad::ShiftLeft(_, x, y)
+            | Quad::ShiftRight(_, x, y)
+            | Quad::Subtract(_, x, y) => vec![x, y],
+            Quad::Assignment(_, x)
+            | Quad::Ifz(x, _)
+            | Quad::Not(_, x)
+            | Quad::SetArg(_, x)
+            | Quad::SetRet(x)
+            | Quad::WriteBool(x)
+            | Quad::WriteInt(x)
+            | Quad::WriteStr(x) => vec![x],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn uses_mut(&mut self) -> Vec<&mut Argument> {
+        match self {
+            Quad::Add(_, x, y)
+            | Quad::And(_, x, y)
+            | Quad::Check(_, x, y, _)
+            | Quad::Divide(_, x, y)
+            | Quad::Equals(_, x, y)
+            | Quad::Greater(_, x, y)
+            | Quad::GreaterEq(_, x, y)
+            | Quad::Less(_, x, y)
+            | Quad::LessEq(_, x, y)
+            | Quad::Multiply(_, x, y)
+            | Quad::NotEq(_, x, y)
+            | Quad::Or(_, x, y)
+            | Quad::ShiftLeft(_, x, y)
+            | Quad::ShiftRight(_, x, y)
+            | Quad::Subtract(_, x, y) => vec![x, y],
+            Quad::Assignment(_, x)
+            | Quad::Ifz(x, _)
+            | Quad::Not(_, x)
+            | Quad::SetArg(_, x)
+            | Quad::SetRet(x)
+            | Quad::WriteBool(x)
+            | Quad::WriteInt(x)
+            | Quad::WriteStr(x) => vec![x],
+            _ => Vec::new(),
+        }
+    }
+}
+
 impl std::fmt::Display for Quad {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -50,17 +226,14 @@ impl std::fmt::Display for Quad {
             Quad::And(w, x, y) => write!(f, "[{w}] := {x} AND64 {y}\n"),
             Quad::Assignment(w, x) => write!(f, "[{w}] := {x}\n"),
             Quad::Call(w) => write!(f, "call fn_{w}\n"),
+            Quad::CallSite(w) => write!(f, "callsite {w}\n"),
+            Quad::Check(check, x, y, message) => write!(f, "check {x} {check} {y} {message}\n"),
             Quad::Divide(w, x, y) => write!(f, "[{w}] := {x} DIV64 {y}\n"),
             Quad::Enter(w) => write!(f, "fn_{w}: enter {w}\n"),
             Quad::Exit => write!(f, "exit\n"),
             Quad::Equals(w, x, y) => write!(f, "[{w}] := {x} EQ64 {y}\n"),
             Quad::GetArg(n, x) => write!(f, "getarg {n} [{x}]\n"),
             Quad::GetRet(w) => write!(f, "getret [{w}]\n"),
-            Quad::Globals(globals) => {
-                write!(f, "[BEGIN GLOBALS]\n")?;
-                write!(f, "{}\n", globals.join("\n"))?;
-                write!(f, "[End GLOBALS]\n")
-            }
             Quad::Goto(w) => write!(f, "goto {w}\n"),
             Quad::Greater(w, x, y) => write!(f, "[{w}] := {x} GT64 {y}\n"),
             Quad::GreaterEq(w, x, y) => write!(f, "[{w}] := {x} GTE64 {y}\n"),
@@ -69,323 +242,441 @@ impl std::fmt::Display for Quad {
             Quad::Leave(w, n) => write!(f, "{w}: leave {n}\n"),
             Quad::Less(w, x, y) => write!(f, "[{w}] := {x} LT64 {y}\n"),
             Quad::LessEq(w, x, y) => write!(f, "[{w}] := {x} LTE64 {y}\n"),
-            Quad::Locals(name, formals, locals, temps) => {
-                write!(f, "[BEGIN {name} LOCALS]\n")?;
-
-                for formal in formals {
-                    let name = &formal.id;
-                    write!(f, "{name} (formal arg of 8 bytes)\n")?;
-                }
-
-                for local in locals {
-                    write!(f, "{local} (local var of 8 bytes)\n")?;
-                }
-
-                for i in temps.clone() {
-                    write!(f, "tmp_{i} (tmp var of 8 bytes)\n")?;
-                }
-
-                write!(f, "[END {name} LOCALS]\n")
-            }
+            Quad::Loc(pos) => write!(f, "loc {pos}\n"),
             Quad::Multiply(w, x, y) => write!(f, "[{w}] := {x} MULT64 {y}\n"),
             Quad::Not(w, x) => write!(f, "[{w}] := NOT64 {x}\n"),
             Quad::NotEq(w, x, y) => write!(f, "[{w}] := {x} NEQ64 {y}\n"),
             Quad::Or(w, x, y) => write!(f, "[{w}] := {x} OR64 {y}\n"),
-            Quad::Read(w) => write!(f, "read {w}\n"),
+            Quad::ReadBool(w, message) => write!(f, "readbool {w} {message}\n"),
+            Quad::ReadInt(w, message) => write!(f, "read {w} {message}\n"),
             Quad::SetArg(n, x) => write!(f, "setarg {n} {x}\n"),
             Quad::SetRet(x) => write!(f, "setret {x}\n"),
+            Quad::ShiftLeft(w, x, y) => write!(f, "[{w}] := {x} SHL64 {y}\n"),
+            Quad::ShiftRight(w, x, y) => write!(f, "[{w}] := {x} SHR64 {y}\n"),
             Quad::Subtract(w, x, y) => write!(f, "[{w}] := {x} SUB64 {y}\n"),
-            Quad::WriteBool(x) | Quad::WriteInt(x) | Quad::WriteStr(x) => write!(f, "write {x}\n"),
+            Quad::TailCall(w) => write!(f, "tailcall fn_{w}\n"),
+            Quad::WriteBool(x) => write!(f, "writebool {x}\n"),
+            Quad::WriteInt(x) | Quad::WriteStr(x) => write!(f, "write {x}\n"),
         }
     }
 }
 
-impl X64Target for Quad {
-    fn compile_x64(&self) -> String {
+impl std::fmt::Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Quad::Add(location, x, y) => {
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!("{str}addq %rax, %rcx\n");
-                format!("{str}{}", x64::write(location, "%rcx"))
-            }
-            Quad::And(location, x, y) => {
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!("{str}andq %rax, %rcx\n");
-                format!("{str}{}", x64::write(location, "%rcx"))
-            }
-            Quad::Assignment(location, value) => {
-                let str = x64::load(value, "%rax");
-                format!("{str}{}", x64::write(location, "%rax"))
-            }
-            Quad::Call(name) => format!("call fn_{name}\n"),
-            Quad::Divide(location, x, y) => {
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!(
-                    "{str}\
-					cqo\n\
-					idivq %rcx\n"
-                );
-                format!("{str}{}", x64::write(location, "%rax"))
-            }
-            Quad::Enter(name) => {
-                let size = x64::get_locals_size();
-                format!(
-                    "fn_{name}: push %rbp\n\
-                	movq %rsp, %rbp\n\
-                	subq ${size}, %rsp\n"
-                )
-            }
-            Quad::Exit => format!(
-                "movq $60, %rax\n\
-                movq $0, %rdi\n\
-                syscall\n"
-            ),
-            Quad::Equals(location, x, y) => {
-                let l_else = intermediate_code::get_lbl();
-                let l_end = intermediate_code::get_lbl();
+            Check::Add => write!(f, "ADD64"),
+            Check::Divide => write!(f, "DIV64"),
+            Check::Multiply => write!(f, "MULT64"),
+            Check::Subtract => write!(f, "SUB64"),
+        }
+    }
+}
 
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!(
-                    "{str}\
-					cmpq %rcx, %rax\n\
-					jne {l_else}\n\
-					movq $1, %rax\n\
-					jmp {l_end}\n\
-					{l_else}: movq $0, %rax\n\
-					{l_end}: nop\n"
-                );
-                format!("{str}{}", x64::write(location, "%rax"))
-            }
-            Quad::GetArg(number, variable) => {
-                let arg_registers = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
-                x64::write(variable, arg_registers[*number])
-            }
-            Quad::GetRet(location) => x64::write(location, "%rax"),
-            Quad::Globals(globals) => {
-                let mut string = format!(
-                    ".globl main\n\
-					.bss\n\
-					.align 32\n\
-					.size FGETS_BUFFER, 1024\n\
-					FGETS_BUFFER: .zero 1024\n\
-					.data\n\
-					true_str: .string \"true\"\n\
-					false_str: .string \"false\"\n\
-					int_fmt: .string \"%d\"\n"
-                );
-
-                for global in globals {
-                    let first_4: String = global.chars().take(4).collect();
-                    if first_4 == "str_" {
-                        let mut str = global.split(" ");
-                        let name = str.next().unwrap();
-                        let value = str.next().unwrap();
-                        string = format!("{string}{name}: .string {value}\n");
-                    }
-
-                    if first_4 == "glb_" {
-                        string = format!("{string}{global}: .zero 8\n");
-                    }
-                }
-
-                format!("{string}.text\n")
-            }
-            Quad::Goto(target) => format!("jmp {target}\n"),
-            Quad::Greater(location, x, y) => {
-                let l_else = intermediate_code::get_lbl();
-                let l_end = intermediate_code::get_lbl();
+impl Quad {
+    /// The condition a comparison checks for
+    fn condition(&self) -> Option<Condition> {
+        match self {
+            Quad::Equals(_, _, _) => Some(Condition::Equal),
+            Quad::Greater(_, _, _) => Some(Condition::Greater),
+            Quad::GreaterEq(_, _, _) => Some(Condition::GreaterEq),
+            Quad::Less(_, _, _) => Some(Condition::Less),
+            Quad::LessEq(_, _, _) => Some(Condition::LessEq),
+            Quad::NotEq(_, _, _) => Some(Condition::NotEqual),
+            _ => None,
+        }
+    }
 
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!(
-                    "{str}\
-					cmpq %rcx, %rax\n\
-					jle {l_else}\n\
-					movq $1, %rax\n\
-					jmp {l_end}\n\
-					{l_else}: movq $0, %rax\n\
-					{l_end}: nop\n"
-                );
-                format!("{str}{}", x64::write(location, "%rax"))
-            }
-            Quad::GreaterEq(location, x, y) => {
-                let l_else = intermediate_code::get_lbl();
-                let l_end = intermediate_code::get_lbl();
+    /// Lowers this quad together with an ifz on its result, by jumping on the
+    /// flags directly. Only valid when nothing reads the result afterwards.
+    pub fn get_fused_x64_instructions(&self, branch: &Quad) -> Option<Vec<Instruction>> {
+        let Quad::Ifz(tested, label) = branch else {
+            return None;
+        };
+        if self.def() != Some(tested) {
+            return None;
+        }
 
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!(
-                    "{str}\
-					cmpq %rcx, %rax\n\
-					jl {l_else}\n\
-					movq $1, %rax\n\
-					jmp {l_end}\n\
-					{l_else}: movq $0, %rax\n\
-					{l_end}: nop\n"
-                );
-                format!("{str}{}", x64::write(location, "%rax"))
-            }
-            Quad::Ifz(condition, label) => {
-                let mut str = x64::load(condition, "%rax");
-                str = format!(
-                    "{str}cmpq $0, %rax\n\
-					je {label}\n"
-                );
+        match self {
+            Quad::Not(_, x) => {
+                let (code, x) = match x {
+                    Argument::Literal(_) => (x64::load(x, "%rax"), Register("%rax")),
+                    _ => (Vec::new(), x64::location(x)),
+                };
 
-                str
+                let test = vec![
+                    Cmp(Immediate(0), x),
+                    Jcc(Condition::NotEqual, label.clone()),
+                ];
+                Some([code, test].concat())
             }
-            Quad::Label(name) => format!("{name}: nop\n"),
-            Quad::Leave(label, _) => format!(
-                "xorq %rax, %rax\n\
-				{label}: addq $4, %rsp\n\
-				leave\n\
-				ret\n"
-            ),
-            Quad::Less(location, x, y) => {
-                let l_else = intermediate_code::get_lbl();
-                let l_end = intermediate_code::get_lbl();
-
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!(
-                    "{str}\
-					cmpq %rcx, %rax\n\
-					jge {l_else}\n\
-					movq $1, %rax\n\
-					jmp {l_end}\n\
-					{l_else}: movq $0, %rax\n\
-					{l_end}: nop\n"
-                );
-                format!("{str}{}", x64::write(location, "%rax"))
+            Quad::Equals(_, x, y)
+            | Quad::Greater(_, x, y)
+            | Quad::GreaterEq(_, x, y)
+            | Quad::Less(_, x, y)
+            | Quad::LessEq(_, x, y)
+            | Quad::NotEq(_, x, y) => {
+                let jump = Jcc(self.condition()?.inverse(), label.clone());
+                Some([compare(x, y), vec![jump]].concat())
             }
-            Quad::LessEq(location, x, y) => {
-                let l_else = intermediate_code::get_lbl();
-                let l_end = intermediate_code::get_lbl();
+            _ => None,
+        }
+    }
 
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!(
-                    "{str}\
-					cmpq %rcx, %rax\n\
-					jg {l_else}\n\
-					movq $1, %rax\n\
-					jmp {l_end}\n\
-					{l_else}: movq $0, %rax\n\
-					{l_end}: nop\n"
-                );
-                format!("{str}{}", x64::write(location, "%rax"))
-            }
-            Quad::Locals(_, formals, locals, temps) => {
-                x64::reset_fn();
+    /// Lowers this quad to x64 instructions for the function being compiled
+    pub fn get_x64_instructions(&self) -> Vec<Instruction> {
+        match self {
+            Quad::Add(location, x, y) => binary_operation(Operation::Add, location, x, y),
+            Quad::And(location, x, y) => binary_operation(Operation::And, location, x, y),
+            Quad::Assignment(location, value) => x64::assign(location, value),
+            Quad::Call(name) => vec![Call(format!("fn_{name}"))],
+            Quad::CallSite(site) => match x64::call_record() {
+                Some([_, _, call_site]) => vec![
+                    Lea(Symbol(site.clone()), Register("%rax")),
+                    Mov(Register("%rax"), call_site),
+                ],
+                None => Vec::new(),
+            },
+            Quad::Check(Check::Divide, x, y, message) => check_division(x, y, message),
+            Quad::Check(check, x, y, message) => {
+                let operation = match check {
+                    Check::Add => Operation::Add,
+                    Check::Multiply => Operation::Imul,
+                    Check::Subtract => Operation::Sub,
+                    Check::Divide => unreachable!(),
+                };
+                let l_valid = intermediate_code::get_lbl();
+                let (code, y) = x64::source(y, "%rcx");
 
-                for formal in formals {
-                    x64::define_local(&formal.id.name);
-                }
+                [
+                    x64::load(x, "%rax"),
+                    code,
+                    vec![
+                        Binary(operation, y, Register("%rax")),
+                        Jcc(Condition::NoOverflow, l_valid.clone()),
+                    ],
+                    runtime_error(message, "dm_overflow"),
+                    vec![Label(l_valid)],
+                ]
+                .concat()
+            }
+            Quad::Divide(location, x, y) => {
+                // idivq has no immediate form
+                let (code, y) = match y {
+                    Argument::Literal(_) => (x64::load(y, "%rcx"), Register("%rcx")),
+                    _ => x64::source(y, "%rcx"),
+                };
 
-                for local in locals {
-                    x64::define_local(&local.name);
-                }
+                [
+                    x64::load(x, "%rax"),
+                    code,
+                    vec![Cqo, Idiv(y)],
+                    x64::write(location, "%rax"),
+                ]
+                .concat()
+            }
+            Quad::Enter(name) => {
+                let size = x64::get_locals_size() as i64;
+                let prologue = [
+                    vec![Function(name.clone())],
+                    cfi("startproc"),
+                    vec![Push(Register("%rbp"))],
+                    cfi("def_cfa_offset 16"),
+                    cfi("offset %rbp, -16"),
+                    vec![Mov(Register("%rsp"), Register("%rbp"))],
+                    cfi("def_cfa_register %rbp"),
+                    vec![Binary(Operation::Sub, Immediate(size), Register("%rsp"))],
+                ]
+                .concat();
 
-                for i in temps.clone() {
-                    x64::define_local(&format!("tmp_{i}"));
-                }
+                // The caller's registers are found where they're saved, above the
+                // return address and %rbp
+                let saved = x64::saved_registers()
+                    .into_iter()
+                    .flat_map(|(register, position)| {
+                        cfi(&format!("offset {register}, -{}", position + 16))
+                    })
+                    .collect();
 
-                "".to_string()
-            }
-            Quad::Multiply(location, x, y) => {
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!("{str}imulq %rax, %rcx\n");
-                format!("{str}{}", x64::write(location, "%rcx"))
-            }
-            Quad::Not(location, x) => {
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}xorq $1, %rax\n");
-                format!("{str}{}", x64::write(location, "%rax"))
+                [
+                    prologue,
+                    x64::save_registers(),
+                    saved,
+                    push_call_record(name),
+                ]
+                .concat()
             }
-            Quad::NotEq(location, x, y) => {
-                let l_else = intermediate_code::get_lbl();
-                let l_end = intermediate_code::get_lbl();
+            Quad::Exit => runtime::exit(),
+            Quad::Equals(location, x, y) => comparison(Condition::Equal, location, x, y),
+            Quad::GetArg(number, variable) => match x64::arg_register(*number) {
+                Some(register) => x64::write(variable, register),
+                None => [
+                    vec![Mov(x64::incoming_arg(*number), Register("%rax"))],
+                    x64::write(variable, "%rax"),
+                ]
+                .concat(),
+            },
+            Quad::GetRet(location) => x64::write(location, "%rax"),
+            Quad::Goto(target) => vec![Jmp(target.clone())],
+            Quad::Greater(location, x, y) => comparison(Condition::Greater, location, x, y),
+            Quad::GreaterEq(location, x, y) => comparison(Condition::GreaterEq, location, x, y),
+            Quad::Ifz(condition, label) => {
+                let (code, condition) = match condition {
+                    Argument::Literal(_) => (x64::load(condition, "%rax"), Register("%rax")),
+                    _ => (Vec::new(), x64::location(condition)),
+                };
 
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!(
-                    "{str}\
-					cmpq %rcx, %rax\n\
-					je {l_else}\n\
-					movq $1, %rax\n\
-					jmp {l_end}\n\
-					{l_else}: movq $0, %rax\n\
-					{l_end}: nop\n"
-                );
-                format!("{str}{}", x64::write(location, "%rax"))
-            }
-            Quad::Or(location, x, y) => {
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!("{str}orq %rax, %rcx\n");
-                format!("{str}{}", x64::write(location, "%rcx"))
-            }
-            Quad::Read(variable) => {
-                let str = format!(
-                    "leaq FGETS_BUFFER(%rip), %rdi\n\
-					movq $1024, %rsi\n\
-					movq stdin(%rip), %rdx\n\
-					call fgets\n\
-					movq %rax, %rdi\n\
-					call atoi\n"
-                );
-                format!("{str}{}", x64::write(variable, "%rax"))
-            }
-            Quad::SetArg(number, variable) => {
-                let arg_registers = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
-                x64::load(variable, arg_registers[*number])
+                let test = vec![
+                    Cmp(Immediate(0), condition),
+                    Jcc(Condition::Equal, label.clone()),
+                ];
+                [code, test].concat()
             }
+            Quad::Label(name) => vec![Label(name.clone())],
+            Quad::Leave(label, _) => [
+                vec![
+                    Binary(Operation::Xor, Register("%rax"), Register("%rax")),
+                    Label(label.clone()),
+                ],
+                pop_call_record("%rcx"),
+                x64::restore_registers(),
+                epilogue(Ret),
+            ]
+            .concat(),
+            Quad::Less(location, x, y) => comparison(Condition::Less, location, x, y),
+            Quad::LessEq(location, x, y) => comparison(Condition::LessEq, location, x, y),
+            Quad::Loc(pos) => match pos.start() {
+                Ok((line, column)) => vec![Loc(line, column)],
+                Err(_) => Vec::new(),
+            },
+            Quad::Multiply(location, x, y) => binary_operation(Operation::Imul, location, x, y),
+            Quad::Not(location, x) => [
+                x64::load(x, "%rax"),
+                vec![Binary(Operation::Xor, Immediate(1), Register("%rax"))],
+                x64::write(location, "%rax"),
+            ]
+            .concat(),
+            Quad::NotEq(location, x, y) => comparison(Condition::NotEqual, location, x, y),
+            Quad::Or(location, x, y) => binary_operation(Operation::Or, location, x, y),
+            Quad::ReadBool(variable, message) => read(variable, "dm_parse_bool", message),
+            Quad::ReadInt(variable, message) => read(variable, "dm_parse_int", message),
+            Quad::SetArg(number, variable) => match x64::arg_register(*number) {
+                Some(register) => x64::load(variable, register),
+                None => [
+                    x64::load(variable, "%rax"),
+                    vec![Mov(Register("%rax"), x64::outgoing_arg(*number))],
+                ]
+                .concat(),
+            },
             Quad::SetRet(argument) => x64::load(argument, "%rax"),
-            Quad::Subtract(location, x, y) => {
-                let mut str = x64::load(x, "%rax");
-                str = format!("{str}{}", x64::load(y, "%rcx"));
-                str = format!("{str}subq %rcx, %rax\n");
-                format!("{str}{}", x64::write(location, "%rax"))
-            }
+            Quad::ShiftLeft(location, x, y) => shift(Operation::Sal, location, x, y),
+            Quad::ShiftRight(location, x, y) => shift(Operation::Sar, location, x, y),
+            Quad::Subtract(location, x, y) => binary_operation(Operation::Sub, location, x, y),
+            // %rax is the only scratch register not holding an argument
+            Quad::TailCall(name) => [
+                pop_call_record("%rax"),
+                x64::restore_registers(),
+                epilogue(Jmp(format!("fn_{name}"))),
+            ]
+            .concat(),
             Quad::WriteBool(argument) => {
                 let l_false = intermediate_code::get_lbl();
                 let l_end = intermediate_code::get_lbl();
 
-                let str = x64::load(argument, "%rax");
-                format!(
-                    "{str}\
-					cmpq $0, %rax\n\
-					je {l_false}\n\
-					movq $true_str, %rdi\n\
-					jmp {l_end}\n\
-					{l_false}: movq $false_str, %rdi\n\
-                	{l_end}: call printf\n"
-                )
-            }
-            Quad::WriteInt(argument) => {
-                let str = x64::load(argument, "%rsi");
-                format!(
-                    "{str}\
-					movq $int_fmt, %rdi\n\
-                	call printf\n"
-                )
+                let write = vec![
+                    Cmp(Immediate(0), Register("%rax")),
+                    Jcc(Condition::Equal, l_false.clone()),
+                    Lea(Symbol("true_str".to_string()), Register("%rdi")),
+                    Jmp(l_end.clone()),
+                    Label(l_false),
+                    Lea(Symbol("false_str".to_string()), Register("%rdi")),
+                    Label(l_end),
+                ];
+
+                [x64::load(argument, "%rax"), write, runtime::write_str()].concat()
             }
+            Quad::WriteInt(argument) => runtime::write_int(argument),
             Quad::WriteStr(argument) => {
                 let name = match argument {
                     Argument::Global(s) => s,
                     _ => unreachable!(),
                 };
 
-                format!(
-                    "movq ${name}, %rdi\n\
-                	call printf\n"
-                )
+                [
+                    vec![Lea(Symbol(name.clone()), Register("%rdi"))],
+                    runtime::write_str(),
+                ]
+                .concat()
             }
         }
     }
 }
+
+/// Call frame information for unwinding, if the program carries debug
+/// information
+fn cfi(directive: &str) -> Vec<Instruction> {
+    match intermediate_code::get_debug_file() {
+        Some(_) => vec![Cfi(directive.to_string())],
+        None => Vec::new(),
+    }
+}
+
+/// Tears down the frame and leaves with the instruction. The function can go on
+/// after it, so its frame is described as it was before.
+fn epilogue(exit: Instruction) -> Vec<Instruction> {
+    [
+        cfi("remember_state"),
+        vec![Leave],
+        cfi("def_cfa %rsp, 8"),
+        vec![exit],
+        cfi("restore_state"),
+    ]
+    .concat()
+}
+
+/// Links the function's record onto the shadow stack of calls that backtraces
+/// are printed from
+fn push_call_record(name: &str) -> Vec<Instruction> {
+    let Some([below, function, call_site]) = x64::call_record() else {
+        return Vec::new();
+    };
+
+    vec![
+        Mov(Symbol("dm_frames".to_string()), Register("%rax")),
+        Mov(Register("%rax"), below.clone()),
+        Lea(Symbol(format!("name_{name}")), Register("%rax")),
+        Mov(Register("%rax"), function),
+        Mov(Immediate(0), call_site),
+        Lea(below, Register("%rax")),
+        Mov(Register("%rax"), Symbol("dm_frames".to_string())),
+    ]
+}
+
+/// Unlinks the function's record from the shadow stack, through a scratch
+/// register
+fn pop_call_record(scratch: &'static str) -> Vec<Instruction> {
+    let Some([below, _, _]) = x64::call_record() else {
+        return Vec::new();
+    };
+
+    vec![
+        Mov(below, Register(scratch)),
+        Mov(Register(scratch), Symbol("dm_frames".to_string())),
+    ]
+}
+
+/// Reads a line and parses it, failing with the message if it's invalid
+fn read(variable: &Argument, parser: &str, message: &str) -> Vec<Instruction> {
+    let l_valid = intermediate_code::get_lbl();
+
+    let parse = vec![
+        Call(parser.to_string()),
+        Cmp(Immediate(0), Register("%rdx")),
+        Jcc(Condition::Equal, l_valid.clone()),
+        Mov(Register("%rdx"), Register("%rsi")),
+        Lea(Symbol(message.to_string()), Register("%rdi")),
+        Call("dm_runtime_error".to_string()),
+        Label(l_valid),
+    ];
+
+    [runtime::read_line(), parse, x64::write(variable, "%rax")].concat()
+}
+
+/// Fails if x / y divides by zero, or overflows on the smallest int over -1
+fn check_division(x: &Argument, y: &Argument, message: &str) -> Vec<Instruction> {
+    let l_nonzero = intermediate_code::get_lbl();
+    let l_valid = intermediate_code::get_lbl();
+
+    let zero = vec![
+        Cmp(Immediate(0), Register("%rcx")),
+        Jcc(Condition::NotEqual, l_nonzero.clone()),
+    ];
+    // Only the smallest int overflows when multiplied by -1
+    let overflow = vec![
+        Label(l_nonzero),
+        Cmp(Immediate(-1), Register("%rcx")),
+        Jcc(Condition::NotEqual, l_valid.clone()),
+        Binary(Operation::Imul, Immediate(-1), Register("%rax")),
+        Jcc(Condition::NoOverflow, l_valid.clone()),
+    ];
+
+    [
+        x64::load(x, "%rax"),
+        x64::load(y, "%rcx"),
+        zero,
+        runtime_error(message, "dm_div_zero"),
+        overflow,
+        runtime_error(message, "dm_overflow"),
+        vec![Label(l_valid)],
+    ]
+    .concat()
+}
+
+/// Fails with the message and then the problem, which are both string labels
+fn runtime_error(message: &str, problem: &str) -> Vec<Instruction> {
+    vec![
+        Lea(Symbol(message.to_string()), Register("%rdi")),
+        Lea(Symbol(problem.to_string()), Register("%rsi")),
+        Call("dm_runtime_error".to_string()),
+    ]
+}
+
+/// Lowers an operation that updates %rax in place with its second operand
+fn binary_operation(
+    operation: Operation,
+    location: &Argument,
+    x: &Argument,
+    y: &Argument,
+) -> Vec<Instruction> {
+    let (code, y) = x64::source(y, "%rcx");
+
+    [
+        x64::load(x, "%rax"),
+        code,
+        vec![Binary(operation, y, Register("%rax"))],
+        x64::write(location, "%rax"),
+    ]
+    .concat()
+}
+
+/// Lowers a shift of %rax, by either a literal or %cl
+fn shift(
+    operation: Operation,
+    location: &Argument,
+    x: &Argument,
+    y: &Argument,
+) -> Vec<Instruction> {
+    let (code, amount) = match y {
+        Argument::Literal(value) => (Vec::new(), Immediate(*value as i64)),
+        _ => (x64::load(y, "%rcx"), Register("%cl")),
+    };
+
+    [
+        x64::load(x, "%rax"),
+        code,
+        vec![Binary(operation, amount, Register("%rax"))],
+        x64::write(location, "%rax"),
+    ]
+    .concat()
+}
+
+/// Lowers a comparison to a 1 or 0 in its location
+fn comparison(
+    condition: Condition,
+    location: &Argument,
+    x: &Argument,
+    y: &Argument,
+) -> Vec<Instruction> {
+    let set = vec![
+        Set(condition, Register("%al")),
+        Movzb(Register("%al"), Register("%rax")),
+    ];
+
+    [compare(x, y), set, x64::write(location, "%rax")].concat()
+}
+
+/// Compares x against y, leaving x in %rax
+fn compare(x: &Argument, y: &Argument) -> Vec<Instruction> {
+    let (code, y) = x64::source(y, "%rcx");
+
+    [x64::load(x, "%rax"), code, vec![Cmp(y, Register("%rax"))]].concat()
+}
@@ -0,0 +1,378 @@
+//! # Static Single Assignment
+//! Converts functions to SSA form and back. Locals that are written more than
+//! once are split into versions like x#1 and x#2, joined by phi functions
+//! where control flow merges. The unversioned name holds the value a variable
+//! has on entry to the function.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+    ops::Range,
+};
+
+use super::{intermediate_code, Argument, Cfg, IrFunction, Liveness, Quad};
+
+/// Picks between incoming values depending on which predecessor ran last
+#[derive(Debug, Clone)]
+pub struct Phi {
+    /// The variable before renaming
+    pub variable: String,
+    pub dest: String,
+    /// Incoming values, in the same order as the block's predecessors
+    pub args: Vec<Argument>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SsaFunction {
+    pub name: String,
+    pub formals: Vec<String>,
+    pub locals: Vec<String>,
+    pub temps: Range<usize>,
+    pub cfg: Cfg,
+    /// Phi functions at the top of each block
+    pub phis: Vec<Vec<Phi>>,
+}
+
+impl SsaFunction {
+    /// Converts a function into SSA form
+    pub fn new(function: &IrFunction) -> Self {
+        let cfg = Cfg::new(function);
+        let phis = vec![Vec::new(); cfg.blocks.len()];
+
+        let mut ssa = Self {
+            name: function.name.clone(),
+            formals: function.formals.clone(),
+            locals: function.locals.clone(),
+            temps: function.temps.clone(),
+            cfg,
+            phis,
+        };
+
+        let renamed = ssa.get_reassigned_locals();
+        let idom = ssa.cfg.immediate_dominators();
+        ssa.insert_phis(&renamed, &idom);
+
+        let mut renamer = Renamer {
+            renamed,
+            stacks: HashMap::new(),
+            counters: HashMap::new(),
+            taken: ssa.get_variables(),
+            children: ssa.cfg.dominator_tree(&idom),
+        };
+
+        if !ssa.cfg.blocks.is_empty() {
+            renamer.rename_block(&mut ssa, 0, true);
+        }
+
+        // Unreachable code never runs, but it still has to be renamed consistently
+        for (block, dominator) in idom.iter().enumerate() {
+            if dominator.is_none() {
+                renamer.rename_block(&mut ssa, block, false);
+            }
+        }
+
+        ssa
+    }
+
+    /// Translates back out of SSA form by replacing each phi with copies along
+    /// the incoming edges. Every phi gets its own copy variable, so the copies
+    /// can't clobber each other even after optimization has moved things around.
+    pub fn to_function(&self) -> IrFunction {
+        let blocks = &self.cfg.blocks;
+        let mut quads: Vec<Vec<Quad>> = blocks.iter().map(|b| b.quads.clone()).collect();
+        let mut fall_through_copies: Vec<Vec<Quad>> = vec![Vec::new(); blocks.len()];
+        let mut split_edges = Vec::new();
+
+        for (block, phis) in self.phis.iter().enumerate() {
+            if phis.is_empty() {
+                continue;
+            }
+
+            // Pull the copies into the phis' real variables
+            let position = match quads[block].first() {
+                Some(quad) if quad.label().is_some() => 1,
+                _ => 0,
+            };
+            for (i, phi) in phis.iter().enumerate() {
+                let copy = Quad::Assignment(
+                    Argument::Local(phi.dest.clone()),
+                    Argument::Local(copy_name(&phi.dest)),
+                );
+                quads[block].insert(position + i, copy);
+            }
+
+            // Fill the copies from each predecessor
+            for (j, predecessor) in blocks[block].predecessors.iter().enumerate() {
+                let copies: Vec<Quad> = phis
+                    .iter()
+                    .map(|phi| {
+                        Quad::Assignment(Argument::Local(copy_name(&phi.dest)), phi.args[j].clone())
+                    })
+                    .collect();
+
+                let predecessor = *predecessor;
+                let predecessor_quads = &mut quads[predecessor];
+
+                if blocks[predecessor].successors.len() == 1 {
+                    let end = match predecessor_quads.last() {
+                        Some(quad) if quad.jump_target().is_some() => predecessor_quads.len() - 1,
+                        _ => predecessor_quads.len(),
+                    };
+                    predecessor_quads.splice(end..end, copies);
+                } else if predecessor + 1 == block {
+                    fall_through_copies[predecessor] = copies;
+                } else {
+                    // Critical edge, so the copies get a block of their own
+                    let label = intermediate_code::get_lbl();
+                    let target = blocks[block].label().unwrap().clone();
+
+                    if let Some(Quad::Goto(l) | Quad::Ifz(_, l)) = predecessor_quads.last_mut() {
+                        *l = label.clone();
+                    }
+
+                    let mut edge = vec![Quad::Label(label)];
+                    edge.extend(copies);
+                    edge.push(Quad::Goto(target));
+                    split_edges.push(edge);
+                }
+            }
+        }
+
+        let mut body = Vec::new();
+        for (block, copies) in quads.into_iter().zip(fall_through_copies) {
+            body.extend(block);
+            body.extend(copies);
+        }
+        body.extend(split_edges.into_iter().flatten());
+
+        let mut function = IrFunction {
+            name: self.name.clone(),
+            formals: self.formals.clone(),
+            locals: self.locals.clone(),
+            temps: self.temps.clone(),
+            body,
+        };
+        declare_new_locals(&mut function);
+        function
+    }
+
+    /// Every variable declared or used in the function
+    fn get_variables(&self) -> HashSet<String> {
+        let mut variables: HashSet<String> = self.formals.iter().cloned().collect();
+        variables.extend(self.locals.iter().cloned());
+        variables.extend(self.temps.clone().map(|i| format!("tmp_{i}")));
+
+        for block in &self.cfg.blocks {
+            for quad in &block.quads {
+                let arguments = quad.uses().into_iter().chain(quad.def());
+                variables.extend(arguments.filter_map(|a| a.local()).cloned());
+            }
+        }
+
+        variables
+    }
+
+    /// Locals with more than one definition, which are the only ones that need versions
+    fn get_reassigned_locals(&self) -> HashSet<String> {
+        let mut definitions: HashMap<&String, usize> = HashMap::new();
+
+        for block in &self.cfg.blocks {
+            for quad in &block.quads {
+                if let Some(name) = quad.def().and_then(|d| d.local()) {
+                    *definitions.entry(name).or_insert(0) += 1;
+                }
+            }
+        }
+
+        definitions
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Places phis at the dominance frontiers of definitions, wherever the
+    /// variable is still live. A phi anywhere else would only copy a value
+    /// nothing reads, which may not even be defined along every edge.
+    fn insert_phis(&mut self, renamed: &HashSet<String>, idom: &[Option<usize>]) {
+        let frontiers = self.cfg.dominance_frontiers(idom);
+        let liveness = Liveness::new(&self.cfg);
+        let mut definition_blocks: HashMap<&String, Vec<usize>> = HashMap::new();
+
+        for (i, block) in self.cfg.blocks.iter().enumerate() {
+            for quad in &block.quads {
+                if let Some(name) = quad.def().and_then(|d| d.local()) {
+                    let blocks = definition_blocks.entry(name).or_default();
+                    if !blocks.contains(&i) {
+                        blocks.push(i);
+                    }
+                }
+            }
+        }
+
+        let mut variables: Vec<&String> = renamed.iter().collect();
+        variables.sort();
+
+        for variable in variables {
+            let mut worklist = definition_blocks[variable].clone();
+            let mut has_phi = HashSet::new();
+
+            while let Some(block) = worklist.pop() {
+                for frontier in &frontiers[block] {
+                    if !liveness.live_in[*frontier].contains(variable) || !has_phi.insert(*frontier)
+                    {
+                        continue;
+                    }
+
+                    let predecessors = self.cfg.blocks[*frontier].predecessors.len();
+                    self.phis[*frontier].push(Phi {
+                        variable: variable.clone(),
+                        dest: variable.clone(),
+                        args: vec![Argument::Local(variable.clone()); predecessors],
+                    });
+
+                    if !definition_blocks[variable].contains(frontier) {
+                        worklist.push(*frontier);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Renaming state for the walk down the dominator tree
+struct Renamer {
+    renamed: HashSet<String>,
+    stacks: HashMap<String, Vec<String>>,
+    counters: HashMap<String, usize>,
+    /// Every variable name already in use
+    taken: HashSet<String>,
+    children: Vec<Vec<usize>>,
+}
+
+impl Renamer {
+    fn rename_block(&mut self, ssa: &mut SsaFunction, block: usize, recurse: bool) {
+        let mut pushed = Vec::new();
+
+        for phi in &mut ssa.phis[block] {
+            phi.dest = self.new_version(&phi.variable);
+            pushed.push(phi.variable.clone());
+        }
+
+        for quad in &mut ssa.cfg.blocks[block].quads {
+            for argument in quad.uses_mut() {
+                if let Argument::Local(name) = argument {
+                    *name = self.current_version(name);
+                }
+            }
+
+            if let Some(Argument::Local(name)) = quad.def_mut() {
+                if self.renamed.contains(name) {
+                    let variable = name.clone();
+                    *name = self.new_version(&variable);
+                    pushed.push(variable);
+                }
+            }
+        }
+
+        for successor in ssa.cfg.blocks[block].successors.clone() {
+            let predecessors = &ssa.cfg.blocks[successor].predecessors;
+            let j = predecessors.iter().position(|p| *p == block).unwrap();
+
+            for phi in &mut ssa.phis[successor] {
+                phi.args[j] = Argument::Local(self.current_version(&phi.variable));
+            }
+        }
+
+        if recurse {
+            for child in self.children[block].clone() {
+                self.rename_block(ssa, child, true);
+            }
+        }
+
+        for variable in pushed {
+            self.stacks.get_mut(&variable).unwrap().pop();
+        }
+    }
+
+    fn current_version(&self, variable: &String) -> String {
+        match self.stacks.get(variable).and_then(|stack| stack.last()) {
+            Some(version) => version.clone(),
+            None => variable.clone(),
+        }
+    }
+
+    /// Makes a new version of a variable. Versions of versions are numbered from
+    /// the original name, so repeated trips through SSA don't pile up suffixes.
+    fn new_version(&mut self, variable: &str) -> String {
+        let base = variable.split('#').next().unwrap().to_string();
+        let counter = self.counters.entry(base.clone()).or_insert(0);
+
+        let mut version = variable.to_string();
+        while self.taken.contains(&version) {
+            *counter += 1;
+            version = format!("{base}#{counter}");
+        }
+        self.taken.insert(version.clone());
+
+        self.stacks
+            .entry(variable.to_string())
+            .or_default()
+            .push(version.clone());
+        version
+    }
+}
+
+/// Name of the variable that carries a phi's value along incoming edges
+fn copy_name(dest: &String) -> String {
+    format!("{dest}'")
+}
+
+/// Adds any variables that only appear in the body to the function's locals
+pub fn declare_new_locals(function: &mut IrFunction) {
+    let mut known: HashSet<String> = function.variables().into_iter().collect();
+
+    for quad in &function.body {
+        let arguments = quad.uses().into_iter().chain(quad.def());
+
+        for name in arguments.filter_map(|a| a.local()) {
+            if known.insert(name.clone()) {
+                function.locals.push(name.clone());
+            }
+        }
+    }
+}
+
+impl Display for Phi {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let args: Vec<String> = self.args.iter().map(|a| format!("{a}")).collect();
+        write!(f, "[[{}]] := PHI({})", self.dest, args.join(", "))
+    }
+}
+
+impl Display for SsaFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "[BEGIN {} SSA]", self.name)?;
+
+        for (i, block) in self.cfg.blocks.iter().enumerate() {
+            let predecessors: Vec<String> =
+                block.predecessors.iter().map(|p| format!("B{p}")).collect();
+            writeln!(f, "B{i} <- [{}]", predecessors.join(", "))?;
+
+            let mut quads = block.quads.iter().peekable();
+            if let Some(quad) = quads.next_if(|quad| quad.label().is_some()) {
+                write!(f, "{quad}")?;
+            }
+
+            for phi in &self.phis[i] {
+                writeln!(f, "{phi}")?;
+            }
+
+            for quad in quads {
+                write!(f, "{quad}")?;
+            }
+        }
+
+        writeln!(f, "[END {} SSA]", self.name)
+    }
+}
@@ -0,0 +1,188 @@
+//! # IR Verifier
+//! Checks that a program is well formed, so a broken pass gets caught where
+//! it happens instead of as a panic or bad assembly in the backend
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+
+use super::{Argument, Cfg, IrFunction, IrProgram, Quad};
+use crate::err;
+
+pub fn verify(program: &IrProgram) -> Result<()> {
+    for function in &program.functions {
+        if let Err(problem) = verify_function(function, program) {
+            return err!("FATAL in {}: {problem}", function.name);
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_function(function: &IrFunction, program: &IrProgram) -> Result<(), String> {
+    match function.body.first() {
+        Some(Quad::Enter(name)) if *name == function.name => (),
+        _ => return Err(format!("Function must start with enter {}", function.name)),
+    }
+
+    let leaves = function
+        .body
+        .iter()
+        .filter(|quad| matches!(quad, Quad::Leave(_, _)));
+    match leaves.count() {
+        0 => return Err("Function has no leave".to_string()),
+        1 => (),
+        _ => return Err("Function has more than one leave".to_string()),
+    }
+
+    let mut labels = HashSet::new();
+    for quad in &function.body {
+        if let Some(label) = quad.label() {
+            if !labels.insert(label) {
+                return Err(format!("Label {label} is defined more than once"));
+            }
+        }
+    }
+
+    let variables: HashSet<String> = function.variables().into_iter().collect();
+    for quad in &function.body {
+        verify_quad(quad, function, program, &variables, &labels)
+            .map_err(|problem| format!("{problem} in `{}`", quad.to_string().trim_end()))?;
+    }
+
+    verify_temps_defined(function)
+}
+
+fn verify_quad(
+    quad: &Quad,
+    function: &IrFunction,
+    program: &IrProgram,
+    variables: &HashSet<String>,
+    labels: &HashSet<&String>,
+) -> Result<(), String> {
+    let is_string = |name: &String| program.strings.iter().any(|s| s.label == *name);
+    let is_global = |name: &String| program.globals.iter().any(|g| g.name == *name);
+
+    for argument in quad.uses().into_iter().chain(quad.def()) {
+        match argument {
+            Argument::Local(name) if !variables.contains(name) => {
+                return Err(format!("Undeclared local {name}"));
+            }
+            Argument::Global(name) if is_string(name) && !matches!(quad, Quad::WriteStr(_)) => {
+                return Err(format!("String {name} used as a value"));
+            }
+            Argument::Global(name) if !is_string(name) && !is_global(name) => {
+                return Err(format!("Undeclared global {name}"));
+            }
+            _ => (),
+        }
+    }
+
+    if let Some(Argument::Literal(_)) = quad.def() {
+        return Err("Literal used as a destination".to_string());
+    }
+
+    if let Some(label) = quad.jump_target() {
+        if !labels.contains(label) {
+            return Err(format!("Jump to undefined label {label}"));
+        }
+    }
+
+    if let Some(callee) = quad.callee() {
+        if program.get_function(callee).is_none() {
+            return Err(format!("Call to undefined function {callee}"));
+        }
+    }
+
+    match quad {
+        Quad::Enter(_) if !std::ptr::eq(quad, &function.body[0]) => {
+            Err("Enter in the middle of a function".to_string())
+        }
+        Quad::Leave(_, name) if *name != function.name => {
+            Err(format!("Leave of {name} in another function"))
+        }
+        Quad::GetArg(0, _) | Quad::SetArg(0, _) => Err("Arguments are numbered from 1".to_string()),
+        Quad::Check(_, _, _, message) | Quad::ReadBool(_, message) | Quad::ReadInt(_, message)
+            if !is_string(message) =>
+        {
+            Err(format!("Error message {message} isn't a string"))
+        }
+        Quad::CallSite(site) if !is_string(site) => Err(format!("Call site {site} isn't a string")),
+        Quad::WriteStr(Argument::Global(name)) if is_string(name) => Ok(()),
+        Quad::WriteStr(_) => Err("Only strings can be written as strings".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Every temporary has to be written on every path to where it's read. Only
+/// temporaries are checked, since source variables can be read unassigned.
+fn verify_temps_defined(function: &IrFunction) -> Result<(), String> {
+    let cfg = Cfg::new(function);
+    let order = cfg.reverse_postorder();
+
+    // None until a block is reached, then the temps defined on every path to it
+    let mut defined_in: Vec<Option<HashSet<&String>>> = vec![None; cfg.blocks.len()];
+    let mut defined_out: Vec<Option<HashSet<&String>>> = vec![None; cfg.blocks.len()];
+    if let Some(entry) = defined_in.first_mut() {
+        *entry = Some(HashSet::new());
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for block in &order {
+            if *block != 0 {
+                let outs = cfg.blocks[*block].predecessors.iter();
+                let mut outs = outs.filter_map(|p| defined_out[*p].as_ref());
+                let Some(first) = outs.next() else {
+                    continue;
+                };
+
+                let mut defined = first.clone();
+                for out in outs {
+                    defined.retain(|name| out.contains(name));
+                }
+                defined_in[*block] = Some(defined);
+            }
+
+            let mut defined = defined_in[*block].clone().unwrap_or_default();
+            for quad in &cfg.blocks[*block].quads {
+                if let Some(Argument::Local(name)) = quad.def() {
+                    defined.insert(name);
+                }
+            }
+
+            if defined_out[*block].as_ref() != Some(&defined) {
+                defined_out[*block] = Some(defined);
+                changed = true;
+            }
+        }
+    }
+
+    for block in order {
+        let mut defined = defined_in[block].clone().unwrap_or_default();
+
+        for quad in &cfg.blocks[block].quads {
+            for argument in quad.uses() {
+                let Argument::Local(name) = argument else {
+                    continue;
+                };
+
+                if name.starts_with("tmp_") && !defined.contains(name) {
+                    let quad = quad.to_string();
+                    return Err(format!(
+                        "{name} may be read before it's written in `{}`",
+                        quad.trim_end()
+                    ));
+                }
+            }
+
+            if let Some(Argument::Local(name)) = quad.def() {
+                defined.insert(name);
+            }
+        }
+    }
+
+    Ok(())
+}
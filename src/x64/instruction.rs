@@ -0,0 +1,175 @@
+//! # x64 Instructions
+//! Structured form of the emitted assembly, so it can still be rewritten
+//! before it's printed.
+
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Register(&'static str),
+    Immediate(i64),
+    /// Offset below %rbp
+    Stack(usize),
+    /// Offset above %rbp, where the caller put arguments passed on the stack
+    Incoming(usize),
+    /// Offset above %rsp, where arguments passed on the stack go for a call
+    Outgoing(usize),
+    /// Data at a symbol, addressed relative to %rip
+    Symbol(String),
+}
+
+/// Condition codes for conditional jumps, from signed comparisons and
+/// arithmetic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+    Overflow,
+    NoOverflow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Add,
+    And,
+    Imul,
+    Or,
+    Sal,
+    Sar,
+    Sub,
+    Xor,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    /// Entry point of a function
+    Function(String),
+    Label(String),
+    /// Call frame information directive, without its .cfi_ prefix
+    Cfi(String),
+    /// Marks the instructions after it as coming from a line and column of
+    /// the source file
+    Loc(usize, usize),
+    /// Operation with a source and a destination, storing into the destination
+    Binary(Operation, Operand, Operand),
+    Call(String),
+    /// Compares the destination against the source
+    Cmp(Operand, Operand),
+    Cqo,
+    Idiv(Operand),
+    Jcc(Condition, String),
+    Jmp(String),
+    Lea(Operand, Operand),
+    Leave,
+    Mov(Operand, Operand),
+    /// Zero extends a byte
+    Movzb(Operand, Operand),
+    Push(Operand),
+    Ret,
+    /// Sets a byte to whether a condition holds
+    Set(Condition, Operand),
+}
+
+impl Condition {
+    /// The condition that holds exactly when this one doesn't
+    pub fn inverse(&self) -> Self {
+        match self {
+            Condition::Equal => Condition::NotEqual,
+            Condition::NotEqual => Condition::Equal,
+            Condition::Less => Condition::GreaterEq,
+            Condition::LessEq => Condition::Greater,
+            Condition::Greater => Condition::LessEq,
+            Condition::GreaterEq => Condition::Less,
+            Condition::Overflow => Condition::NoOverflow,
+            Condition::NoOverflow => Condition::Overflow,
+        }
+    }
+}
+
+impl Instruction {
+    /// The label this instruction may jump to
+    pub fn jump_target(&self) -> Option<&String> {
+        match self {
+            Instruction::Jcc(_, label) | Instruction::Jmp(label) => Some(label),
+            _ => None,
+        }
+    }
+
+    /// Whether this only tells the assembler about the code around it, without
+    /// being an instruction itself
+    pub fn is_directive(&self) -> bool {
+        matches!(self, Instruction::Cfi(_) | Instruction::Loc(_, _))
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "{register}"),
+            Operand::Immediate(value) => write!(f, "${value}"),
+            Operand::Stack(position) => write!(f, "-{position}(%rbp)"),
+            Operand::Incoming(position) => write!(f, "{position}(%rbp)"),
+            Operand::Outgoing(position) => write!(f, "{position}(%rsp)"),
+            Operand::Symbol(symbol) => write!(f, "{symbol}(%rip)"),
+        }
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Equal => write!(f, "e"),
+            Condition::NotEqual => write!(f, "ne"),
+            Condition::Less => write!(f, "l"),
+            Condition::LessEq => write!(f, "le"),
+            Condition::Greater => write!(f, "g"),
+            Condition::GreaterEq => write!(f, "ge"),
+            Condition::Overflow => write!(f, "o"),
+            Condition::NoOverflow => write!(f, "no"),
+        }
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Add => write!(f, "addq"),
+            Operation::And => write!(f, "andq"),
+            Operation::Imul => write!(f, "imulq"),
+            Operation::Or => write!(f, "orq"),
+            Operation::Sal => write!(f, "salq"),
+            Operation::Sar => write!(f, "sarq"),
+            Operation::Sub => write!(f, "subq"),
+            Operation::Xor => write!(f, "xorq"),
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instruction::Function(name) => writeln!(f, "fn_{name}:"),
+            Instruction::Label(label) => writeln!(f, "{label}:"),
+            Instruction::Cfi(directive) => writeln!(f, ".cfi_{directive}"),
+            Instruction::Loc(line, column) => writeln!(f, ".loc 1 {line} {column}"),
+            Instruction::Binary(operation, x, y) => writeln!(f, "{operation} {x}, {y}"),
+            Instruction::Call(name) => writeln!(f, "call {name}"),
+            Instruction::Cmp(x, y) => writeln!(f, "cmpq {x}, {y}"),
+            Instruction::Cqo => writeln!(f, "cqo"),
+            Instruction::Idiv(x) => writeln!(f, "idivq {x}"),
+            Instruction::Jcc(condition, label) => writeln!(f, "j{condition} {label}"),
+            Instruction::Jmp(label) => writeln!(f, "jmp {label}"),
+            Instruction::Lea(x, y) => writeln!(f, "leaq {x}, {y}"),
+            Instruction::Leave => writeln!(f, "leave"),
+            Instruction::Mov(x, y) => writeln!(f, "movq {x}, {y}"),
+            Instruction::Movzb(x, y) => writeln!(f, "movzbq {x}, {y}"),
+            Instruction::Push(x) => writeln!(f, "push {x}"),
+            Instruction::Ret => writeln!(f, "ret"),
+            Instruction::Set(condition, x) => writeln!(f, "set{condition} {x}"),
+        }
+    }
+}
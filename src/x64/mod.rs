@@ -0,0 +1,208 @@
+//! # x64 Backend
+//! State and helpers shared by the lowering of each quad. Variables live either
+//! in a register picked by the allocator or in a stack slot below %rbp.
+
+use std::collections::HashMap;
+
+use crate::three_ac::Argument;
+use instruction::{Instruction, Instruction::Mov, Operand, Operand::*};
+
+pub mod instruction;
+pub mod peephole;
+pub mod register_allocation;
+pub mod runtime;
+
+/// Registers arguments are passed in, in order
+pub const ARG_REGISTERS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+
+/// Register an argument is passed in, counting from 1 like the quads do.
+/// Arguments past the last register are passed on the stack instead.
+pub fn arg_register(number: usize) -> Option<&'static str> {
+    ARG_REGISTERS.get(number - 1).copied()
+}
+
+/// Where an argument passed on the stack is read from, above the saved %rbp
+/// and the return address
+pub fn incoming_arg(number: usize) -> Operand {
+    Incoming(16 + (number - ARG_REGISTERS.len() - 1) * 8)
+}
+
+/// Where an argument passed on the stack is stored for a call. The bottom of
+/// the frame is kept free for them, so %rsp stays aligned.
+pub fn outgoing_arg(number: usize) -> Operand {
+    Outgoing((number - ARG_REGISTERS.len() - 1) * 8)
+}
+
+/// Layout of the function being compiled
+struct Frame {
+    /// Register or stack slot of each variable
+    locals: HashMap<String, Operand>,
+    /// Callee-saved registers in use, along with the slots they're saved in
+    saved_registers: Vec<(&'static str, usize)>,
+    /// Slot of the function's record on the shadow stack of calls, if it has one
+    call_record: Option<usize>,
+    slots: usize,
+    /// Slots at the bottom of the frame for arguments passed on the stack
+    outgoing_slots: usize,
+}
+
+static mut FRAME: Option<Frame> = None;
+
+pub trait X64Target {
+    fn compile_x64(&self) -> String;
+}
+
+fn frame() -> &'static mut Frame {
+    unsafe { FRAME.as_mut().unwrap() }
+}
+
+pub fn get_locals_size() -> usize {
+    let size = (frame().slots + frame().outgoing_slots) * 8;
+
+    size + (16 - size % 16) % 16
+}
+
+fn new_slot() -> usize {
+    let frame = frame();
+    frame.slots += 1;
+    frame.slots * 8
+}
+
+pub fn define_local(name: &String) {
+    let position = new_slot();
+    frame().locals.insert(name.clone(), Stack(position));
+}
+
+/// Keeps a variable in a register, saving the register on entry if the
+/// caller expects it to survive the call
+pub fn define_register(name: &str, register: &'static str) {
+    let saved = frame().saved_registers.iter().any(|(r, _)| *r == register);
+    if register_allocation::CALLEE_SAVED.contains(&register) && !saved {
+        let position = new_slot();
+        frame().saved_registers.push((register, position));
+    }
+
+    frame().locals.insert(name.to_string(), Register(register));
+}
+
+/// Reserves a record of the function on the shadow stack of calls, which
+/// backtraces walk. It's three slots: the record below it, the function's name
+/// and where the function is making a call from.
+pub fn define_call_record() {
+    new_slot();
+    new_slot();
+    frame().call_record = Some(new_slot());
+}
+
+/// Makes room for the arguments of calls with this many of them
+pub fn define_outgoing_args(count: usize) {
+    let slots = count.saturating_sub(ARG_REGISTERS.len());
+    frame().outgoing_slots = frame().outgoing_slots.max(slots);
+}
+
+/// The fields of the function's record, in order, if it has one
+pub fn call_record() -> Option<[Operand; 3]> {
+    let position = frame().call_record?;
+    Some([Stack(position), Stack(position - 8), Stack(position - 16)])
+}
+
+pub fn reset_fn() {
+    unsafe {
+        FRAME = Some(Frame {
+            locals: HashMap::new(),
+            saved_registers: Vec::new(),
+            call_record: None,
+            slots: 0,
+            outgoing_slots: 0,
+        });
+    }
+}
+
+/// Callee-saved registers in use, along with the slots they're saved in
+pub fn saved_registers() -> Vec<(&'static str, usize)> {
+    frame().saved_registers.clone()
+}
+
+pub fn save_registers() -> Vec<Instruction> {
+    let saved = &frame().saved_registers;
+    saved
+        .iter()
+        .map(|(register, position)| Mov(Register(register), Stack(*position)))
+        .collect()
+}
+
+pub fn restore_registers() -> Vec<Instruction> {
+    let saved = &frame().saved_registers;
+    saved
+        .iter()
+        .map(|(register, position)| Mov(Stack(*position), Register(register)))
+        .collect()
+}
+
+/// The operand an instruction can read an argument through directly, if any.
+/// Literals only fit when they sign extend from 32 bits.
+pub fn operand(arg: &Argument) -> Option<Operand> {
+    match arg {
+        Argument::Literal(value) => {
+            let value = *value as i64;
+            (i32::try_from(value).is_ok()).then_some(Immediate(value))
+        }
+        _ => Some(location(arg)),
+    }
+}
+
+/// Where a variable is kept
+pub fn location(arg: &Argument) -> Operand {
+    match arg {
+        Argument::Literal(_) => unreachable!(),
+        Argument::Local(name) => frame().locals.get(name).unwrap().clone(),
+        Argument::Global(name) => Symbol(format!("glb_{name}")),
+    }
+}
+
+/// Code and operand for reading an argument, going through a scratch register
+/// only when it can't be read directly
+pub fn source(arg: &Argument, scratch: &'static str) -> (Vec<Instruction>, Operand) {
+    match operand(arg) {
+        Some(operand) => (Vec::new(), operand),
+        None => (load(arg, scratch), Register(scratch)),
+    }
+}
+
+pub fn load(arg: &Argument, register: &'static str) -> Vec<Instruction> {
+    let value = match arg {
+        Argument::Literal(value) => Immediate(*value as i64),
+        _ => location(arg),
+    };
+
+    mov(value, Register(register))
+}
+
+pub fn write(arg: &Argument, register: &'static str) -> Vec<Instruction> {
+    mov(Register(register), location(arg))
+}
+
+/// Copies one argument into another, through %rax only if both are in memory
+pub fn assign(location: &Argument, value: &Argument) -> Vec<Instruction> {
+    match (register_of(location), register_of(value)) {
+        (Some(register), _) => load(value, register),
+        (None, Some(register)) => write(location, register),
+        (None, None) => [load(value, "%rax"), write(location, "%rax")].concat(),
+    }
+}
+
+fn register_of(arg: &Argument) -> Option<&'static str> {
+    match arg.local().map(|name| frame().locals.get(name).unwrap()) {
+        Some(Register(register)) => Some(register),
+        _ => None,
+    }
+}
+
+/// A move, unless it would move something onto itself
+fn mov(from: Operand, to: Operand) -> Vec<Instruction> {
+    if from == to {
+        Vec::new()
+    } else {
+        vec![Mov(from, to)]
+    }
+}
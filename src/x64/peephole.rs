@@ -0,0 +1,165 @@
+//! # Peephole Optimization
+//! Cleans up the patterns lowering one quad at a time leaves behind in a
+//! function's instructions
+
+use std::collections::HashMap;
+
+use super::instruction::{Condition, Instruction, Instruction::*, Operand::*};
+
+pub fn optimize(mut instructions: Vec<Instruction>) -> Vec<Instruction> {
+    loop {
+        let before = instructions.clone();
+
+        instructions = fuse_tests(instructions);
+        instructions = remove_reloads(instructions);
+        instructions = remove_jumps_to_next(instructions);
+        instructions = remove_unreachable(instructions);
+        instructions = remove_unused_labels(instructions);
+
+        if instructions == before {
+            return instructions;
+        }
+    }
+}
+
+/// Counts the jumps to each label
+fn get_references(instructions: &[Instruction]) -> HashMap<&String, usize> {
+    let mut references = HashMap::new();
+    for label in instructions.iter().filter_map(|i| i.jump_target()) {
+        *references.entry(label).or_insert(0) += 1;
+    }
+
+    references
+}
+
+/// Branches straight on a comparison's flags when its result is materialized
+/// as a 1 or 0 only to be tested against zero again
+fn fuse_tests(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let mut result = Vec::new();
+    let mut i = 0;
+
+    while i < instructions.len() {
+        match fuse_test(&instructions[i..]) {
+            Some((length, fused)) => {
+                result.extend(fused);
+                i += length;
+            }
+            None => {
+                result.push(instructions[i].clone());
+                i += 1;
+            }
+        }
+    }
+
+    result
+}
+
+/// Matches a comparison that's set into %rax, optionally stored somewhere,
+/// and then tested. Nothing in between touches the flags, so the test can be
+/// replaced by a jump on the comparison itself.
+fn fuse_test(window: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    let [Cmp(_, _), Set(condition, Register("%al")), Movzb(Register("%al"), Register("%rax")), rest @ ..] =
+        window
+    else {
+        return None;
+    };
+
+    let (stored, rest) = match rest {
+        [Mov(Register("%rax"), location), rest @ ..] => (Some(location), rest),
+        _ => (None, rest),
+    };
+
+    let [Cmp(Immediate(0), tested), Jcc(Condition::Equal, target), ..] = rest else {
+        return None;
+    };
+
+    if *tested != Register("%rax") && Some(tested) != stored {
+        return None;
+    }
+
+    let length = window.len() - rest.len();
+    let mut fused = window[..length].to_vec();
+    fused.push(Jcc(condition.inverse(), target.clone()));
+
+    Some((length + 2, fused))
+}
+
+/// Removes moves that copy a value back to where it just came from, and reads
+/// of a value straight from the register it was just stored from
+fn remove_reloads(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let mut result: Vec<Instruction> = Vec::new();
+
+    for instruction in instructions {
+        let last = result.iter().rev().find(|i| !i.is_directive());
+        match (last, &instruction) {
+            (_, Mov(from, to)) if from == to => continue,
+            (Some(Mov(from, to)), Mov(x, y)) if x == to && y == from => continue,
+            (Some(Mov(from @ Register(_), to)), Mov(x, y))
+                if x == to && !matches!(to, Register(_)) =>
+            {
+                let reload = Mov(from.clone(), y.clone());
+                result.push(reload);
+            }
+            _ => result.push(instruction),
+        }
+    }
+
+    result
+}
+
+/// Removes jumps to a label that comes right after them anyway
+fn remove_jumps_to_next(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let mut result = Vec::new();
+
+    for (i, instruction) in instructions.iter().enumerate() {
+        if let Some(target) = instruction.jump_target() {
+            let mut labels = instructions[i + 1..].iter().map_while(|next| match next {
+                Label(label) => Some(Some(label)),
+                next if next.is_directive() => Some(None),
+                _ => None,
+            });
+
+            if labels.any(|label| label == Some(target)) {
+                continue;
+            }
+        }
+
+        result.push(instruction.clone());
+    }
+
+    result
+}
+
+/// Removes instructions after a jump or return that only a label could reach.
+/// Directives describe what comes after them, so they stay.
+fn remove_unreachable(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let mut result = Vec::new();
+    let mut reachable = true;
+
+    for instruction in instructions {
+        match instruction {
+            Function(_) | Label(_) => reachable = true,
+            _ if instruction.is_directive() => (),
+            _ if !reachable => continue,
+            Jmp(_) | Ret => reachable = false,
+            _ => (),
+        }
+
+        result.push(instruction);
+    }
+
+    result
+}
+
+fn remove_unused_labels(instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let references = get_references(&instructions);
+
+    instructions
+        .iter()
+        .filter(|instruction| match instruction {
+            Label(label) => references.contains_key(label),
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
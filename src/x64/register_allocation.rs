@@ -0,0 +1,188 @@
+//! # Register Allocation
+//! Linear scan allocation of a function's variables to registers. Each variable
+//! gets one live interval over the function's quads, and intervals that can't
+//! all fit in registers at once are spilled to the stack.
+//!
+//! %rax, %rcx and %rdx are never allocated, since quad lowering uses them as
+//! scratch registers. Registers that calls clobber are only given to variables
+//! that aren't live across a call.
+
+use std::collections::HashMap;
+
+use super::arg_register;
+use crate::three_ac::{Cfg, IrFunction, Liveness, Quad};
+
+/// Registers a call may clobber, other than the scratch registers
+pub const CALLER_SAVED: [&str; 6] = ["%rsi", "%rdi", "%r8", "%r9", "%r10", "%r11"];
+
+/// Registers a function has to restore before returning
+pub const CALLEE_SAVED: [&str; 5] = ["%rbx", "%r12", "%r13", "%r14", "%r15"];
+
+/// Quads from start to end that a variable's value must survive. It's written
+/// at the end of the start quad and last read at the start of the end quad.
+#[derive(Debug, Clone)]
+struct Interval {
+    variable: String,
+    start: usize,
+    end: usize,
+}
+
+/// A register that quads from start to end need for themselves
+#[derive(Debug, Clone)]
+struct Reservation {
+    register: &'static str,
+    start: usize,
+    end: usize,
+}
+
+impl Interval {
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.start < end && start < self.end
+    }
+}
+
+/// Picks a register for as many of a function's variables as possible.
+/// Variables left out need a stack slot.
+pub fn allocate(function: &IrFunction) -> HashMap<String, &'static str> {
+    let mut intervals = get_intervals(function);
+    intervals.sort_by_key(|interval| (interval.start, interval.end));
+    let reservations = get_reservations(&function.body);
+
+    let usable = |interval: &Interval, register: &'static str| {
+        !reservations
+            .iter()
+            .any(|r| r.register == register && interval.overlaps(r.start, r.end))
+    };
+
+    let mut registers = HashMap::new();
+    let mut active: Vec<(Interval, &'static str)> = Vec::new();
+
+    for interval in intervals {
+        active.retain(|(other, _)| other.end > interval.start);
+
+        let free = CALLER_SAVED.iter().chain(&CALLEE_SAVED).find(|register| {
+            !active.iter().any(|(_, r)| r == *register) && usable(&interval, register)
+        });
+
+        if let Some(register) = free {
+            registers.insert(interval.variable.clone(), *register);
+            active.push((interval, register));
+            continue;
+        }
+
+        // Out of registers, so spill whichever interval lasts the longest
+        let furthest = active
+            .iter()
+            .enumerate()
+            .filter(|(_, (other, register))| {
+                other.end > interval.end && usable(&interval, register)
+            })
+            .max_by_key(|(_, (other, _))| other.end)
+            .map(|(i, _)| i);
+
+        if let Some(i) = furthest {
+            let (spilled, register) = active.remove(i);
+            registers.remove(&spilled.variable);
+            registers.insert(interval.variable.clone(), register);
+            active.push((interval, register));
+        }
+    }
+
+    registers
+}
+
+/// Live interval of every variable in the function, covering the gaps in
+/// between uses so that a single register can hold it the whole time
+fn get_intervals(function: &IrFunction) -> Vec<Interval> {
+    let cfg = Cfg::new(function);
+    let liveness = Liveness::new(&cfg);
+    let mut ranges: HashMap<&String, (usize, usize)> = HashMap::new();
+
+    let mut extend = |variable, position| {
+        let range = ranges.entry(variable).or_insert((position, position));
+        range.0 = range.0.min(position);
+        range.1 = range.1.max(position);
+    };
+
+    let mut position = 0;
+    for (i, block) in cfg.blocks.iter().enumerate() {
+        for variable in &liveness.live_in[i] {
+            extend(variable, position);
+        }
+
+        for quad in &block.quads {
+            let arguments = quad.uses().into_iter().chain(quad.def());
+            for variable in arguments.filter_map(|a| a.local()) {
+                extend(variable, position);
+            }
+            position += 1;
+        }
+
+        // Values live out of a block have to survive its last quad too
+        for variable in &liveness.live_out[i] {
+            extend(variable, position);
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(variable, (start, end))| Interval {
+            variable: variable.clone(),
+            start,
+            end,
+        })
+        .collect()
+}
+
+/// Registers that calls and argument passing take over. Arguments set before a
+/// call have to stay put until the call, and incoming arguments until they're
+/// read. Arguments passed on the stack don't need a register.
+fn get_reservations(quads: &[Quad]) -> Vec<Reservation> {
+    let mut reservations = Vec::new();
+
+    for (position, quad) in quads.iter().enumerate() {
+        match quad {
+            Quad::Call(_)
+            | Quad::ReadBool(_, _)
+            | Quad::ReadInt(_, _)
+            | Quad::WriteBool(_)
+            | Quad::WriteInt(_)
+            | Quad::WriteStr(_) => {
+                for register in CALLER_SAVED {
+                    reservations.push(Reservation {
+                        register,
+                        start: position,
+                        end: position,
+                    });
+                }
+            }
+            Quad::SetArg(number, _) => {
+                let Some(register) = arg_register(*number) else {
+                    continue;
+                };
+                let call = quads[position..]
+                    .iter()
+                    .position(|quad| quad.callee().is_some())
+                    .map_or(position, |offset| position + offset);
+
+                reservations.push(Reservation {
+                    register,
+                    start: position,
+                    end: call,
+                });
+            }
+            Quad::GetArg(number, _) => {
+                if let Some(register) = arg_register(*number) {
+                    reservations.push(Reservation {
+                        register,
+                        start: 0,
+                        end: position,
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+
+    reservations
+}
@@ -0,0 +1,514 @@
+//! # Runtime
+//! What the generated code calls to do input and output. Either libc, which the
+//! program links against like any C program, or a small library of our own that
+//! the compiler emits into the program and that talks to the kernel directly.
+//!
+//! The builtin runtime buffers output until the buffer fills, the program reads
+//! input or the program exits.
+
+use clap::ValueEnum;
+
+use super::instruction::{Instruction, Instruction::*, Operand::*, Operation};
+use crate::three_ac::{Argument, INIT_FN};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Runtime {
+    /// printf, fgets and atoi from the C library
+    Libc,
+    /// Routines emitted into the program, so it doesn't need libc
+    Builtin,
+}
+
+static mut RUNTIME: Runtime = Runtime::Libc;
+
+pub fn set_runtime(runtime: Runtime) {
+    unsafe { RUNTIME = runtime }
+}
+
+pub fn get_runtime() -> Runtime {
+    unsafe { RUNTIME }
+}
+
+/// Writes the string whose address is in %rdi. With libc that's fputs, since
+/// printf would take the string as its format.
+pub fn write_str() -> Vec<Instruction> {
+    match get_runtime() {
+        Runtime::Libc => vec![
+            Mov(Symbol("stdout".to_string()), Register("%rsi")),
+            call_libc("fputs"),
+        ],
+        Runtime::Builtin => vec![Call("dm_write_str".to_string())],
+    }
+}
+
+/// Writes an integer
+pub fn write_int(argument: &Argument) -> Vec<Instruction> {
+    match get_runtime() {
+        Runtime::Libc => [
+            super::load(argument, "%rsi"),
+            vec![Lea(Symbol("int_fmt".to_string()), Register("%rdi"))],
+            call_printf(),
+        ]
+        .concat(),
+        Runtime::Builtin => [
+            super::load(argument, "%rdi"),
+            vec![Call("dm_write_int".to_string())],
+        ]
+        .concat(),
+    }
+}
+
+/// Reads a line, leaving the address of it in %rdi. At the end of the input
+/// the line is empty.
+pub fn read_line() -> Vec<Instruction> {
+    vec![
+        Call("dm_read_line".to_string()),
+        Lea(Symbol("DM_LINE_BUFFER".to_string()), Register("%rdi")),
+    ]
+}
+
+/// Ends the program successfully, after writing out any buffered output
+pub fn exit() -> Vec<Instruction> {
+    let exit = match get_runtime() {
+        Runtime::Libc => call_libc("exit"),
+        Runtime::Builtin => Call("dm_exit".to_string()),
+    };
+
+    vec![Mov(Immediate(0), Register("%rdi")), exit]
+}
+
+/// Calls into libc go through the PLT, since it can be loaded anywhere
+fn call_libc(function: &str) -> Instruction {
+    Call(format!("{function}@PLT"))
+}
+
+/// Calls printf, which being variadic takes how many vector registers hold
+/// arguments in %al
+fn call_printf() -> Vec<Instruction> {
+    vec![
+        Binary(Operation::Xor, Register("%rax"), Register("%rax")),
+        call_libc("printf"),
+    ]
+}
+
+/// Where the program starts, which runs the global initializers and then the
+/// program's main, followed by the runtime's routines. Either way functions
+/// are called with the stack aligned to 16 bytes.
+pub fn entry() -> String {
+    match get_runtime() {
+        // libc calls main, and gets the exit code from its return
+        Runtime::Libc => format!(
+            ".globl main\n\
+			.data\n\
+			int_fmt: .string \"%ld\"\n\
+			.text\n\
+			main: subq $8, %rsp\n\
+			call fn_{INIT_FN}\n\
+			addq $8, %rsp\n\
+			jmp fn_main\n\
+			{LIBC_LIBRARY}{LIBRARY}"
+        ),
+        // The kernel jumps to _start with nothing to return to
+        Runtime::Builtin => format!(
+            ".globl _start\n\
+			.text\n\
+			_start: call fn_{INIT_FN}\n\
+			call fn_main\n\
+			movq %rax, %rdi\n\
+			call dm_exit\n\
+			{BUILTIN_LIBRARY}{LIBRARY}"
+        ),
+    }
+}
+
+/// Flags the linker needs to link against the runtime
+pub fn link_flags(pie: bool) -> Vec<&'static str> {
+    match (get_runtime(), pie) {
+        (Runtime::Libc, true) => vec!["-pie"],
+        (Runtime::Libc, false) => vec!["-no-pie"],
+        (Runtime::Builtin, true) => vec!["-nostdlib", "-static-pie"],
+        (Runtime::Builtin, false) => vec!["-nostdlib", "-static"],
+    }
+}
+
+/// The builtin runtime's routines. They only touch registers a call may
+/// clobber.
+const BUILTIN_LIBRARY: &str = "\
+.bss
+.align 32
+DM_OUT_BUFFER: .zero 4096
+dm_out_len: .zero 8
+DM_LINE_BUFFER: .zero 1024
+.text
+
+# Writes out everything buffered
+dm_flush:
+leaq DM_OUT_BUFFER(%rip), %rsi
+movq dm_out_len(%rip), %rdx
+.Lflush_loop:
+testq %rdx, %rdx
+jle .Lflush_done
+movq $1, %rax
+movq $1, %rdi
+syscall
+# Output that can't be written is dropped, like stdio does
+testq %rax, %rax
+jle .Lflush_done
+addq %rax, %rsi
+subq %rax, %rdx
+jmp .Lflush_loop
+.Lflush_done:
+movq $0, dm_out_len(%rip)
+ret
+
+# Buffers the string whose address is in %rdi
+dm_write_str:
+movq dm_out_len(%rip), %rcx
+leaq DM_OUT_BUFFER(%rip), %rdx
+.Lstr_loop:
+movzbq (%rdi), %rax
+testq %rax, %rax
+je .Lstr_done
+cmpq $4096, %rcx
+jne .Lstr_put
+movq %rcx, dm_out_len(%rip)
+push %rdi
+call dm_flush
+pop %rdi
+movq $0, %rcx
+leaq DM_OUT_BUFFER(%rip), %rdx
+jmp .Lstr_loop
+.Lstr_put:
+movb %al, (%rdx,%rcx)
+incq %rcx
+incq %rdi
+jmp .Lstr_loop
+.Lstr_done:
+movq %rcx, dm_out_len(%rip)
+ret
+
+# Buffers %rdi in decimal. Negating the smallest integer leaves it as is, which
+# the unsigned division then reads as its magnitude.
+dm_write_int:
+subq $40, %rsp
+movq %rdi, %rax
+movq %rax, %r8
+leaq 32(%rsp), %rsi
+movb $0, (%rsi)
+testq %rax, %rax
+jns .Lint_digits
+negq %rax
+.Lint_digits:
+movq $10, %rcx
+.Lint_loop:
+movq $0, %rdx
+divq %rcx
+addq $48, %rdx
+decq %rsi
+movb %dl, (%rsi)
+testq %rax, %rax
+jne .Lint_loop
+testq %r8, %r8
+jns .Lint_write
+decq %rsi
+movb $45, (%rsi)
+.Lint_write:
+movq %rsi, %rdi
+call dm_write_str
+addq $40, %rsp
+ret
+
+# Reads a line from stdin into DM_LINE_BUFFER, without its newline. Whatever
+# doesn't fit is dropped, leaving a character in the last byte before the NUL
+# so the parsers can tell.
+dm_read_line:
+call dm_flush
+movq $0, %r8
+.Lline_loop:
+movq $0, %rax
+movq $0, %rdi
+leaq DM_LINE_BUFFER(%rip), %rsi
+addq %r8, %rsi
+movq $1, %rdx
+syscall
+cmpq $1, %rax
+jne .Lline_done
+leaq DM_LINE_BUFFER(%rip), %rsi
+cmpb $10, (%rsi,%r8)
+je .Lline_done
+cmpq $1023, %r8
+je .Lline_loop
+incq %r8
+jmp .Lline_loop
+.Lline_done:
+leaq DM_LINE_BUFFER(%rip), %rsi
+movb $0, (%rsi,%r8)
+ret
+
+# Writes the string whose address is in %rdi straight to stderr
+dm_write_err:
+movq %rdi, %rsi
+movq $0, %rdx
+.Lerr_length:
+cmpb $0, (%rsi,%rdx)
+je .Lerr_write
+incq %rdx
+jmp .Lerr_length
+.Lerr_write:
+movq $1, %rax
+movq $2, %rdi
+syscall
+ret
+
+# Writes out what's buffered, then the strings at %rdi and %rsi and the
+# backtrace to stderr, and exits with status 1
+dm_runtime_error:
+push %rsi
+push %rdi
+call dm_flush
+pop %rdi
+call dm_write_err
+pop %rdi
+call dm_write_err
+call dm_backtrace
+movq $1, %rdi
+movq $60, %rax
+syscall
+
+# Writes out what's buffered and exits with status %rdi
+dm_exit:
+push %rdi
+call dm_flush
+pop %rdi
+movq $60, %rax
+syscall
+";
+
+/// Routines that use libc
+const LIBC_LIBRARY: &str = "\
+.bss
+.align 32
+DM_LINE_BUFFER: .zero 1024
+.text
+
+# Reads a line from stdin into DM_LINE_BUFFER. fgets leaves the buffer alone at
+# the end of the input, so it's cleared first, and leaves the rest of a line
+# that doesn't fit, so that's dropped.
+dm_read_line:
+subq $8, %rsp
+movb $0, DM_LINE_BUFFER(%rip)
+leaq DM_LINE_BUFFER(%rip), %rdi
+movq $1024, %rsi
+movq stdin(%rip), %rdx
+call fgets@PLT
+leaq DM_LINE_BUFFER(%rip), %rdi
+call strlen@PLT
+cmpq $1023, %rax
+jne .Lline_done
+leaq DM_LINE_BUFFER(%rip), %rdi
+cmpb $10, 1022(%rdi)
+je .Lline_done
+.Lline_rest:
+movq stdin(%rip), %rdi
+call fgetc@PLT
+cmpl $10, %eax
+je .Lline_done
+cmpl $-1, %eax
+jne .Lline_rest
+.Lline_done:
+addq $8, %rsp
+ret
+
+# Writes the string whose address is in %rdi to stderr
+dm_write_err:
+movq stderr(%rip), %rsi
+jmp fputs@PLT
+
+# Writes out what's buffered, then the strings at %rdi and %rsi and the
+# backtrace to stderr, and exits with status 1
+dm_runtime_error:
+push %rdi
+push %rsi
+subq $8, %rsp
+movq stdout(%rip), %rdi
+call fflush@PLT
+movq 16(%rsp), %rdi
+call dm_write_err
+movq 8(%rsp), %rdi
+call dm_write_err
+call dm_backtrace
+movq $1, %rdi
+call exit@PLT
+";
+
+/// Routines either runtime uses. Parsers leave the value in %rax, and in %rdx
+/// either 0 or the address of what's wrong with the input.
+///
+/// With backtraces, each function links a record onto dm_frames while it runs:
+/// the record below it, its name, and where it's making a call from, or 0.
+const LIBRARY: &str = "\
+.bss
+dm_frames: .zero 8
+.data
+dm_not_int: .string \"input is not an int\\n\"
+dm_int_range: .string \"input is out of range for an int\\n\"
+dm_not_bool: .string \"input is not true or false\\n\"
+dm_line_long: .string \"input line is too long\\n\"
+dm_div_zero: .string \"division by zero\\n\"
+dm_overflow: .string \"integer overflow\\n\"
+dm_frame_in: .string \"  in \"
+dm_newline: .string \"\\n\"
+.text
+
+# Writes a line to stderr for each function running, from the innermost out,
+# with where its caller called it from
+dm_backtrace:
+push %rbx
+movq dm_frames(%rip), %rbx
+.Lframe_loop:
+testq %rbx, %rbx
+je .Lframe_done
+leaq dm_frame_in(%rip), %rdi
+call dm_write_err
+movq 8(%rbx), %rdi
+call dm_write_err
+movq (%rbx), %rbx
+testq %rbx, %rbx
+je .Lframe_end
+movq 16(%rbx), %rdi
+testq %rdi, %rdi
+je .Lframe_end
+call dm_write_err
+.Lframe_end:
+leaq dm_newline(%rip), %rdi
+call dm_write_err
+jmp .Lframe_loop
+.Lframe_done:
+pop %rbx
+ret
+
+# Advances %rdi past whitespace, leaving the next character in %rcx
+dm_skip_space:
+movzbq (%rdi), %rcx
+cmpq $32, %rcx
+je .Lspace_next
+leaq -9(%rcx), %rdx
+cmpq $4, %rdx
+ja .Lspace_done
+.Lspace_next:
+incq %rdi
+jmp dm_skip_space
+.Lspace_done:
+ret
+
+# Fails with %rdx if the line at %rdi has more than 1022 characters besides its
+# newline, which is more than the buffer holds. Otherwise %rdx is 0.
+dm_check_line:
+movq $0, %rdx
+.Lcheck_length:
+cmpb $0, (%rdi,%rdx)
+je .Lcheck_end
+incq %rdx
+jmp .Lcheck_length
+.Lcheck_end:
+testq %rdx, %rdx
+je .Lcheck_valid
+cmpb $10, -1(%rdi,%rdx)
+jne .Lcheck_newline
+decq %rdx
+.Lcheck_newline:
+cmpq $1022, %rdx
+ja .Lcheck_long
+.Lcheck_valid:
+movq $0, %rdx
+ret
+.Lcheck_long:
+leaq dm_line_long(%rip), %rdx
+ret
+
+# Parses the string at %rdi as an int with whitespace around it. %r8 is 1 if
+# it's negative, and %r9 is the largest magnitude it can have.
+dm_parse_int:
+call dm_check_line
+testq %rdx, %rdx
+jne .Lint_long
+call dm_skip_space
+movq $0, %r8
+cmpq $45, %rcx
+jne .Lint_plus
+movq $1, %r8
+incq %rdi
+jmp .Lint_start
+.Lint_plus:
+cmpq $43, %rcx
+jne .Lint_start
+incq %rdi
+.Lint_start:
+movabsq $0x7fffffffffffffff, %r9
+addq %r8, %r9
+movq $0, %rax
+movzbq (%rdi), %rcx
+subq $48, %rcx
+cmpq $9, %rcx
+ja .Lint_invalid
+.Lint_digit:
+movq $10, %rdx
+mulq %rdx
+jc .Lint_range
+addq %rcx, %rax
+jc .Lint_range
+cmpq %r9, %rax
+ja .Lint_range
+incq %rdi
+movzbq (%rdi), %rcx
+subq $48, %rcx
+cmpq $9, %rcx
+jbe .Lint_digit
+call dm_skip_space
+testq %rcx, %rcx
+jne .Lint_invalid
+testq %r8, %r8
+je .Lint_valid
+negq %rax
+.Lint_valid:
+movq $0, %rdx
+ret
+.Lint_invalid:
+leaq dm_not_int(%rip), %rdx
+ret
+.Lint_range:
+leaq dm_int_range(%rip), %rdx
+ret
+.Lint_long:
+ret
+
+# Parses the string at %rdi as true or false with whitespace around it
+dm_parse_bool:
+call dm_check_line
+testq %rdx, %rdx
+jne .Lbool_long
+call dm_skip_space
+cmpl $0x65757274, (%rdi)
+jne .Lbool_false
+movq $1, %rax
+addq $4, %rdi
+jmp .Lbool_end
+.Lbool_false:
+cmpl $0x736c6166, (%rdi)
+jne .Lbool_invalid
+cmpb $101, 4(%rdi)
+jne .Lbool_invalid
+movq $0, %rax
+addq $5, %rdi
+.Lbool_end:
+call dm_skip_space
+testq %rcx, %rcx
+jne .Lbool_invalid
+movq $0, %rdx
+ret
+.Lbool_invalid:
+leaq dm_not_bool(%rip), %rdx
+ret
+.Lbool_long:
+ret
+";